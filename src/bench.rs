@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::{get_byte_0xF000, ChipContext, EmulatorConfig};
+
+const CLASS_NAMES: [&str; 16] = [
+    "0nnn cls/ret",
+    "1nnn jp",
+    "2nnn call",
+    "3xkk se",
+    "4xkk sne",
+    "5xy0 se",
+    "6xkk ld",
+    "7xkk add",
+    "8xyn alu",
+    "9xy0 sne",
+    "Annn ld i",
+    "Bnnn jp v0",
+    "Cxkk rnd",
+    "Dxyn drw",
+    "Exnn skp/sknp",
+    "Fxnn misc",
+];
+
+/* "bench ROM [--instructions N]", runs the ROM without a window as fast as
+ * possible and reports the interpreter throughput
+ */
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut rom_path: Option<PathBuf> = None;
+    let mut instructions: u64 = 10_000_000;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--instructions" => {
+                let value = args.next().unwrap_or_default();
+                instructions = value
+                    .parse()
+                    .map_err(|_| format!("invalid instruction count \"{}\"", value))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown argument \"{}\"", arg)),
+            _ => rom_path = Some(PathBuf::from(arg)),
+        }
+    }
+    let rom_path = rom_path.ok_or("usage: bench ROM [--instructions N]")?;
+
+    let mut chip8 = ChipContext::reset();
+    chip8.load_program(&rom_path)?;
+
+    let instructions_per_frame = (EmulatorConfig::INSTRUCTIONS_PER_SECOND / EmulatorConfig::FRAMES_PER_SECOND) as u64;
+    let mut class_counts = [0u64; 16];
+    let mut draw_time = Duration::ZERO;
+
+    let started = Instant::now();
+    for executed in 0..instructions {
+        if executed % instructions_per_frame == 0 {
+            chip8.update_timers();
+            chip8.vblank_ready = true;
+        }
+
+        let class = get_byte_0xF000(chip8.fetch_opcode()) as usize;
+        class_counts[class] += 1;
+        if class == 0xD {
+            let draw_started = Instant::now();
+            chip8.exec_opcode();
+            draw_time += draw_started.elapsed();
+        }
+        else {
+            chip8.exec_opcode();
+        }
+        // A faulting instruction doesn't advance PC, timing it over and over measures nothing
+        if let Some(fault) = chip8.fault.take() {
+            return Err(fault);
+        }
+    }
+    let elapsed = started.elapsed();
+
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    println!("rom:              {}", rom_path.display());
+    println!("instructions:     {}", instructions);
+    println!("time:             {:.3} s", seconds);
+    println!("instructions/sec: {:.0}", instructions as f64 / seconds);
+    println!("time in Dxyn:     {:.3} s ({:.1}%)", draw_time.as_secs_f64(), 100.0 * draw_time.as_secs_f64() / seconds);
+    println!();
+    println!("{:<16} {:>12} {:>7}", "class", "count", "share");
+    for (class, count) in class_counts.iter().enumerate() {
+        if *count == 0 {
+            continue;
+        }
+        println!("{:<16} {:>12} {:>6.2}%", CLASS_NAMES[class], count, 100.0 * *count as f64 / instructions.max(1) as f64);
+    }
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+
+use crate::symbols::SymbolMap;
+use crate::{get_byte_0xF000, get_bytes_0x0FFF, ChipContext};
+
+fn call_target(chip8: &ChipContext, call_site: u16) -> Option<u16> {
+    let opcode = chip8.opcode_at(call_site);
+    if get_byte_0xF000(opcode) == 0x2 {
+        Some(get_bytes_0x0FFF(opcode))
+    }
+    else {
+        None
+    }
+}
+
+/* PC and the call sites on the stack from the innermost call out, each
+ * with the subroutine it called
+ */
+pub fn frames(chip8: &ChipContext, symbols: &SymbolMap) -> Vec<String> {
+    let mut frames = vec![format!("#0  {:03X}  {}", chip8.PC, symbols.describe(chip8.PC))];
+    let depth = (chip8.SP as usize).min(chip8.stack.len());
+    for (frame, call_site) in chip8.stack[..depth].iter().rev().enumerate() {
+        let mut line = format!("#{:<2} {:03X}  {}", frame + 1, call_site, symbols.describe(*call_site));
+        if let Some(target) = call_target(chip8, *call_site) {
+            line += &format!(" -> {}", symbols.describe(target));
+        }
+        frames.push(line);
+    }
+    frames
+}
+
+pub fn backtrace(chip8: &ChipContext, symbols: &SymbolMap) -> String {
+    frames(chip8, symbols).join("\n")
+}
+
+/* Call before the instruction at PC runs. A call from a call site that is
+ * still on the stack means the subroutine it entered last time was left
+ * with a jump instead of ret and every round leaks a stack entry, or that
+ * the subroutine recurses through this call.
+ */
+pub fn unbalanced_call(chip8: &ChipContext, symbols: &SymbolMap) -> Option<String> {
+    let opcode = chip8.fetch_opcode();
+    if get_byte_0xF000(opcode) != 0x2 {
+        return None;
+    }
+    let depth = (chip8.SP as usize).min(chip8.stack.len());
+    let frame = chip8.stack[..depth].iter().position(|call_site| *call_site == chip8.PC)?;
+    Some(format!(
+        "unbalanced call at {:03X} ({}): the call to {} from here at stack depth {} hasn't returned, {} entries leaked unless it recurses",
+        chip8.PC,
+        symbols.describe(chip8.PC),
+        symbols.describe(get_bytes_0x0FFF(opcode)),
+        frame + 1,
+        depth - frame,
+    ))
+}
+
+/* Call before a ret at PC runs, with the call sites of the calls seen so
+ * far. The stack only changes outside of call and ret when it is edited,
+ * and code at a call site can be overwritten, either way ret doesn't go
+ * back to where the innermost call was made.
+ */
+pub fn unbalanced_return(chip8: &ChipContext, calls: &[u16], symbols: &SymbolMap) -> Option<String> {
+    if chip8.fetch_opcode() != 0x00EE || chip8.SP == 0 {
+        return None;
+    }
+    let return_site = chip8.stack[(chip8.SP as usize - 1).min(chip8.stack.len() - 1)];
+    match calls.last() {
+        Some(call_site) if *call_site != return_site => Some(format!(
+            "unbalanced ret at {:03X} ({}): returns after {}, but the innermost call was made at {}",
+            chip8.PC,
+            symbols.describe(chip8.PC),
+            symbols.describe(return_site),
+            symbols.describe(*call_site),
+        )),
+        None => Some(format!(
+            "unbalanced ret at {:03X} ({}): returns after {}, but no call is pending",
+            chip8.PC,
+            symbols.describe(chip8.PC),
+            symbols.describe(return_site),
+        )),
+        Some(_) if call_target(chip8, return_site).is_none() => Some(format!(
+            "unbalanced ret at {:03X} ({}): returns after {}, which holds {:04X} instead of a call",
+            chip8.PC,
+            symbols.describe(chip8.PC),
+            symbols.describe(return_site),
+            chip8.opcode_at(return_site),
+        )),
+        Some(_) => None,
+    }
+}
+
+/* Follows calls and returns next to the stack to stop on the ones that
+ * don't pair up, each call site and ret is reported once since recursion
+ * would report a call site again on every level
+ */
+pub struct StackChecker {
+    // Call sites of the calls that haven't returned yet
+    calls: Vec<u16>,
+    reported: HashSet<u16>,
+}
+
+impl StackChecker {
+    pub fn new() -> StackChecker {
+        StackChecker {
+            calls: Vec::new(),
+            reported: HashSet::new(),
+        }
+    }
+
+    pub fn rom_started(&mut self) {
+        self.calls.clear();
+        self.reported.clear();
+    }
+
+    /* Call before the instruction at PC runs, the diagnostic if it unbalances the stack */
+    pub fn before_instruction(&mut self, chip8: &ChipContext, symbols: &SymbolMap) -> Option<String> {
+        if self.reported.contains(&chip8.PC) {
+            return None;
+        }
+        let problem = unbalanced_call(chip8, symbols).or_else(|| unbalanced_return(chip8, &self.calls, symbols))?;
+        self.reported.insert(chip8.PC);
+        // Go on from the stack as it is, an edit is reported once rather than on every ret
+        let depth = (chip8.SP as usize).min(chip8.stack.len());
+        self.calls = chip8.stack[..depth].to_vec();
+        Some(problem)
+    }
+
+    /* Call after the instruction ran, with the PC and SP from before it */
+    pub fn after_instruction(&mut self, chip8: &ChipContext, pc: u16, sp: u8) {
+        if chip8.SP > sp {
+            self.calls.push(pc);
+        }
+        else if chip8.SP < sp {
+            self.calls.pop();
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::EmulatorConfig;
+use crate::palette::Palette;
+
+type FrameBuffer = [[u8; 32]; 64];
+
+/* Palette indices of the framebuffer, row by row, every CHIP-8 pixel
+ * repeated scale x scale times
+ */
+fn scaled_indices(frame_buffer: &FrameBuffer, scale: u32) -> Vec<u8> {
+    let width = EmulatorConfig::SCREEN_WIDTH * scale;
+    let height = EmulatorConfig::SCREEN_HEIGHT * scale;
+    let mut indices = Vec::with_capacity((width * height) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            indices.push(frame_buffer[(x / scale) as usize][(y / scale) as usize] & 0x3);
+        }
+    }
+    indices
+}
+
+/* Unique file name in the capture directory, e.g. captures/screenshot-1700000000123.png */
+pub fn capture_path(prefix: &str, extension: &str) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(EmulatorConfig::CAPTURE_DIRECTORY)
+        .map_err(|error| format!("cannot create {}: {}", EmulatorConfig::CAPTURE_DIRECTORY, error))?;
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    Ok(Path::new(EmulatorConfig::CAPTURE_DIRECTORY).join(format!("{}-{}.{}", prefix, millis, extension)))
+}
+
+pub fn save_screenshot(path: &Path, frame_buffer: &FrameBuffer, palette: &Palette, scale: u32) -> Result<(), String> {
+    let scale = scale.max(1);
+    let rgb: Vec<u8> = scaled_indices(frame_buffer, scale)
+        .iter()
+        .flat_map(|index| {
+            let color = palette.colors[*index as usize];
+            [color.r, color.g, color.b]
+        })
+        .collect();
+
+    let file = File::create(path).map_err(|error| format!("cannot create {}: {}", path.display(), error))?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        EmulatorConfig::SCREEN_WIDTH * scale,
+        EmulatorConfig::SCREEN_HEIGHT * scale);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&rgb))
+        .map_err(|error| format!("cannot write {}: {}", path.display(), error))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RecordingFormat {
+    // Single animated GIF
+    Gif,
+    // Directory with one numbered PNG per changed frame
+    PngSequence,
+}
+
+impl RecordingFormat {
+    pub fn from_path(path: &Path) -> RecordingFormat {
+        match path.extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("gif") => RecordingFormat::Gif,
+            _ => RecordingFormat::PngSequence,
+        }
+    }
+}
+
+enum RecordingOutput {
+    Gif(gif::Encoder<BufWriter<File>>),
+    PngSequence { directory: PathBuf, frame_index: u32 },
+}
+
+/* Records the frames handed to add_frame, consecutive identical frames are
+ * merged so a mostly static screen doesn't grow the file
+ */
+pub struct Recorder {
+    path: PathBuf,
+    output: RecordingOutput,
+    palette: Palette,
+    scale: u32,
+    started: Instant,
+    duration: Option<Duration>,
+    pending_frame: Option<FrameBuffer>,
+    pending_since: Instant,
+}
+
+impl Recorder {
+    pub fn start(path: &Path, palette: &Palette, scale: u32, duration: Option<Duration>) -> Result<Recorder, String> {
+        let scale = scale.max(1);
+        let output = match RecordingFormat::from_path(path) {
+            RecordingFormat::Gif => {
+                let file = File::create(path).map_err(|error| format!("cannot create {}: {}", path.display(), error))?;
+                let global_palette: Vec<u8> = palette.colors
+                    .iter()
+                    .flat_map(|color| [color.r, color.g, color.b])
+                    .collect();
+                let mut encoder = gif::Encoder::new(
+                    BufWriter::new(file),
+                    (EmulatorConfig::SCREEN_WIDTH * scale) as u16,
+                    (EmulatorConfig::SCREEN_HEIGHT * scale) as u16,
+                    &global_palette)
+                    .map_err(|error| format!("cannot write {}: {}", path.display(), error))?;
+                let _ = encoder.set_repeat(gif::Repeat::Infinite);
+                RecordingOutput::Gif(encoder)
+            }
+            RecordingFormat::PngSequence => {
+                std::fs::create_dir_all(path).map_err(|error| format!("cannot create {}: {}", path.display(), error))?;
+                RecordingOutput::PngSequence { directory: path.to_path_buf(), frame_index: 0 }
+            }
+        };
+
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            output,
+            palette: palette.clone(),
+            scale,
+            started: Instant::now(),
+            duration,
+            pending_frame: None,
+            pending_since: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /* True once the requested time span has been recorded */
+    pub fn is_done(&self) -> bool {
+        match self.duration {
+            Some(duration) => self.started.elapsed() >= duration,
+            None => false,
+        }
+    }
+
+    fn write_pending(&mut self) -> Result<(), String> {
+        let Some(frame_buffer) = self.pending_frame else {
+            return Ok(());
+        };
+
+        match &mut self.output {
+            RecordingOutput::Gif(encoder) => {
+                let width = (EmulatorConfig::SCREEN_WIDTH * self.scale) as u16;
+                let height = (EmulatorConfig::SCREEN_HEIGHT * self.scale) as u16;
+                let mut frame = gif::Frame::from_indexed_pixels(width, height, scaled_indices(&frame_buffer, self.scale), None);
+                // GIF delays are in hundredths of a second
+                frame.delay = (self.pending_since.elapsed().as_millis() / 10).clamp(1, u16::MAX as u128) as u16;
+                encoder
+                    .write_frame(&frame)
+                    .map_err(|error| format!("cannot write {}: {}", self.path.display(), error))
+            }
+            RecordingOutput::PngSequence { directory, frame_index } => {
+                let path = directory.join(format!("frame-{:06}.png", frame_index));
+                *frame_index += 1;
+                save_screenshot(&path, &frame_buffer, &self.palette, self.scale)
+            }
+        }
+    }
+
+    pub fn add_frame(&mut self, frame_buffer: &FrameBuffer) -> Result<(), String> {
+        if self.pending_frame.as_ref() == Some(frame_buffer) {
+            return Ok(());
+        }
+        self.write_pending()?;
+        self.pending_frame = Some(*frame_buffer);
+        self.pending_since = Instant::now();
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<PathBuf, String> {
+        self.write_pending()?;
+        Ok(self.path)
+    }
+}
@@ -0,0 +1,237 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use crate::romdb::RomDatabase;
+use crate::{ChipContext, EmulatorConfig};
+
+/* A byte a cheat can search or freeze, memory addresses are written in hex
+ * and registers as V0-VF
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum Location {
+    Memory(u16),
+    Register(u8),
+}
+
+impl Location {
+    pub fn parse(text: &str) -> Option<Location> {
+        match text.strip_prefix(['V', 'v']) {
+            Some(register) if register.len() == 1 => u8::from_str_radix(register, 16).ok().map(Location::Register),
+            _ => u16::from_str_radix(text.trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|address| *address < 0x1000)
+                .map(Location::Memory),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Location::Memory(address) => format!("{:03X}", address),
+            Location::Register(register) => format!("V{:X}", register),
+        }
+    }
+
+    pub fn read(&self, chip8: &ChipContext) -> u8 {
+        match self {
+            Location::Memory(address) => chip8.memory[*address as usize],
+            Location::Register(register) => chip8.registers[*register as usize],
+        }
+    }
+
+    fn write(&self, chip8: &mut ChipContext, value: u8) {
+        match self {
+            Location::Memory(address) => chip8.memory[*address as usize] = value,
+            Location::Register(register) => chip8.registers[*register as usize] = value,
+        }
+    }
+
+    fn all() -> impl Iterator<Item = Location> {
+        (0..0x1000).map(Location::Memory).chain((0..16).map(Location::Register))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SearchFilter {
+    Exact(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl SearchFilter {
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match self {
+            SearchFilter::Exact(value) => current == *value,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Unchanged => current == previous,
+            SearchFilter::Increased => current > previous,
+            SearchFilter::Decreased => current < previous,
+        }
+    }
+}
+
+/* Narrows down the bytes holding a game value by searching memory and the
+ * registers repeatedly, and freezes bytes to a value every frame
+ */
+pub struct CheatEngine {
+    // Remaining candidates with their value at the last search, None before a search was started
+    candidates: Option<Vec<(Location, u8)>>,
+    frozen: Vec<(Location, u8)>,
+    // Where the cheats of the running ROM are saved
+    cheat_path: Option<PathBuf>,
+}
+
+fn parse_value(text: &str) -> Result<u8, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("invalid value \"{}\", expected 0-255 or 0x00-0xFF", text))
+}
+
+impl CheatEngine {
+    const LIST_LIMIT: usize = 20;
+
+    pub const HELP: &'static str = "\
+new                          start a search with a snapshot of memory and V0-VF
+search VALUE                 keep the bytes equal to VALUE (starts a search if needed)
+changed | unchanged          keep the bytes that changed or didn't since the last search
+increased | decreased        keep the bytes that went up or down since the last search
+list                         show the remaining candidates
+freeze LOCATION [VALUE]      hold a byte (hex address or V0-VF) at VALUE every frame
+unfreeze LOCATION            release a frozen byte
+cheats                       list the frozen bytes";
+
+    pub fn new() -> CheatEngine {
+        CheatEngine {
+            candidates: None,
+            frozen: Vec::new(),
+            cheat_path: None,
+        }
+    }
+
+    /* Loads the cheats saved for the ROM now in memory */
+    pub fn rom_started(&mut self, chip8: &ChipContext) {
+        let path = Path::new(EmulatorConfig::CHEAT_DIRECTORY).join(format!("{}.txt", RomDatabase::sha1(chip8.program())));
+        self.candidates = None;
+        self.frozen = match std::fs::read_to_string(&path) {
+            Ok(text) => Self::parse_cheats(&text),
+            Err(_) => Vec::new(),
+        };
+        if !self.frozen.is_empty() {
+            println!("loaded {} cheats from {}", self.frozen.len(), path.display());
+        }
+        self.cheat_path = Some(path);
+    }
+
+    /* One "LOCATION VALUE" per line, the value in hex, # starts a comment */
+    fn parse_cheats(text: &str) -> Vec<(Location, u8)> {
+        text.lines()
+            .filter_map(|line| {
+                let mut fields = line.split('#').next()?.split_whitespace();
+                let location = Location::parse(fields.next()?)?;
+                let value = u8::from_str_radix(fields.next()?, 16).ok()?;
+                Some((location, value))
+            })
+            .collect()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.cheat_path else {
+            return Ok(());
+        };
+        let mut text = String::new();
+        for (location, value) in &self.frozen {
+            let _ = writeln!(text, "{} {:02X}", location.name(), value);
+        }
+        std::fs::create_dir_all(EmulatorConfig::CHEAT_DIRECTORY)
+            .and_then(|_| std::fs::write(path, text))
+            .map_err(|error| format!("cannot save cheats to {}: {}", path.display(), error))
+    }
+
+    /* Call once per frame */
+    pub fn apply(&self, chip8: &mut ChipContext) {
+        for (location, value) in &self.frozen {
+            location.write(chip8, *value);
+        }
+    }
+
+    fn search(&mut self, filter: SearchFilter, chip8: &ChipContext) -> Result<String, String> {
+        let candidates = match (self.candidates.take(), filter) {
+            (Some(candidates), _) => candidates,
+            (None, SearchFilter::Exact(_)) => Location::all().map(|location| (location, location.read(chip8))).collect(),
+            (None, _) => return Err(String::from("no search running, start one with new or search VALUE")),
+        };
+        let remaining: Vec<(Location, u8)> = candidates
+            .into_iter()
+            .filter(|(location, previous)| filter.matches(*previous, location.read(chip8)))
+            .map(|(location, _)| (location, location.read(chip8)))
+            .collect();
+        let message = format!("{} candidates left", remaining.len());
+        self.candidates = Some(remaining);
+        Ok(message)
+    }
+
+    fn list(&self) -> Result<String, String> {
+        let candidates = self.candidates.as_ref().ok_or("no search running")?;
+        let mut text = String::new();
+        for (location, value) in candidates.iter().take(Self::LIST_LIMIT) {
+            let _ = writeln!(text, "{:>4} = {:3} (0x{:02X})", location.name(), value, value);
+        }
+        if candidates.len() > Self::LIST_LIMIT {
+            let _ = writeln!(text, "... {} more", candidates.len() - Self::LIST_LIMIT);
+        }
+        let _ = write!(text, "{} candidates", candidates.len());
+        Ok(text)
+    }
+
+    /* Runs a console command, None if it isn't a cheat command */
+    pub fn command(&mut self, words: &[&str], chip8: &mut ChipContext) -> Option<Result<String, String>> {
+        let result = match words {
+            ["new"] => {
+                self.candidates = Some(Location::all().map(|location| (location, location.read(chip8))).collect());
+                Ok(format!("{} candidates", Location::all().count()))
+            }
+            ["search", value] => parse_value(value).and_then(|value| self.search(SearchFilter::Exact(value), chip8)),
+            ["changed"] => self.search(SearchFilter::Changed, chip8),
+            ["unchanged"] => self.search(SearchFilter::Unchanged, chip8),
+            ["increased"] => self.search(SearchFilter::Increased, chip8),
+            ["decreased"] => self.search(SearchFilter::Decreased, chip8),
+            ["list"] => self.list(),
+            ["freeze", location, value @ ..] if value.len() <= 1 => {
+                Location::parse(location)
+                    .ok_or(format!("invalid location \"{}\"", location))
+                    .and_then(|location| {
+                        let value = match value.first() {
+                            Some(value) => parse_value(value)?,
+                            None => location.read(chip8),
+                        };
+                        self.frozen.retain(|(frozen, _)| *frozen != location);
+                        self.frozen.push((location, value));
+                        location.write(chip8, value);
+                        self.save()?;
+                        Ok(format!("{} frozen at {}", location.name(), value))
+                    })
+            }
+            ["unfreeze", location] => {
+                Location::parse(location)
+                    .ok_or(format!("invalid location \"{}\"", location))
+                    .and_then(|location| {
+                        self.frozen.retain(|(frozen, _)| *frozen != location);
+                        self.save()?;
+                        Ok(format!("{} released", location.name()))
+                    })
+            }
+            ["cheats"] => {
+                let cheats: Vec<String> = self.frozen
+                    .iter()
+                    .map(|(location, value)| format!("{} = {}", location.name(), value))
+                    .collect();
+                Ok(if cheats.is_empty() { String::from("no cheats") } else { cheats.join("\n") })
+            }
+            _ => return None,
+        };
+        Some(result)
+    }
+}
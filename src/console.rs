@@ -0,0 +1,30 @@
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+
+/* Reads commands from stdin on a background thread so the main loop can
+ * poll for them without blocking
+ */
+pub struct Console {
+    receiver: Receiver<String>,
+}
+
+impl Console {
+    pub fn start() -> Console {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        println!("console ready, type help for a list of commands");
+        Console { receiver }
+    }
+
+    /* Next complete line, None if nothing was typed since the last call */
+    pub fn poll(&self) -> Option<String> {
+        self.receiver.try_recv().ok()
+    }
+}
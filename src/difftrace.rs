@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use crate::symbols::SymbolMap;
+use crate::trace::TraceRecord;
+use crate::{get_byte_0x0F00, get_byte_0xF000, ChipContext, EmulatorConfig, Quirks};
+
+// Matching instructions printed before the divergence
+const CONTEXT_LINES: usize = 8;
+
+const USAGE: &str = "usage: difftrace ROM TRACE [--quirks default|chip8|schip|xochip] [--instructions-per-frame N] [--sync-timers]";
+
+fn quirks_from_name(name: &str) -> Option<Quirks> {
+    match name {
+        "default" => Some(Quirks::DEFAULT),
+        "chip8" => Some(Quirks::COSMAC_VIP),
+        "schip" => Some(Quirks::SUPER_CHIP),
+        "xochip" => Some(Quirks::XO_CHIP),
+        _ => None,
+    }
+}
+
+/* Differences between the reference record and our state before the same
+ * instruction, empty if they agree
+ */
+fn compare_state(expected: &TraceRecord, actual: &TraceRecord, compare_timers: bool) -> Vec<String> {
+    let mut differences = Vec::new();
+    if expected.pc != actual.pc {
+        differences.push(format!("PC: expected {:03X}, got {:03X}", expected.pc, actual.pc));
+    }
+    if expected.opcode != actual.opcode {
+        differences.push(format!("opcode: expected {:04X}, got {:04X}", expected.opcode, actual.opcode));
+    }
+    for (index, (expected_value, actual_value)) in expected.registers.iter().zip(actual.registers.iter()).enumerate() {
+        if expected_value != actual_value {
+            differences.push(format!("V{:X}: expected {:02X}, got {:02X}", index, expected_value, actual_value));
+        }
+    }
+    if expected.i != actual.i {
+        differences.push(format!("I: expected {:03X}, got {:03X}", expected.i, actual.i));
+    }
+    if expected.sp != actual.sp {
+        differences.push(format!("SP: expected {:X}, got {:X}", expected.sp, actual.sp));
+    }
+    if compare_timers && expected.delay != actual.delay {
+        differences.push(format!("DT: expected {:02X}, got {:02X}", expected.delay, actual.delay));
+    }
+    if compare_timers && expected.sound != actual.sound {
+        differences.push(format!("ST: expected {:02X}, got {:02X}", expected.sound, actual.sound));
+    }
+    differences
+}
+
+/* "difftrace ROM TRACE", replays the key presses of a reference trace from
+ * another emulator and reports the first instruction where the state differs
+ */
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let mut quirks = Quirks::DEFAULT;
+    let mut instructions_per_frame = (EmulatorConfig::INSTRUCTIONS_PER_SECOND / EmulatorConfig::FRAMES_PER_SECOND) as u64;
+    let mut sync_timers = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().unwrap_or_default();
+                quirks = quirks_from_name(&name)
+                    .ok_or(format!("unknown quirk profile \"{}\", expected default, chip8, schip or xochip", name))?;
+            }
+            "--instructions-per-frame" => {
+                let value = args.next().unwrap_or_default();
+                instructions_per_frame = value
+                    .parse()
+                    .ok()
+                    .filter(|value| *value > 0)
+                    .ok_or(format!("invalid instructions per frame \"{}\"", value))?;
+            }
+            "--sync-timers" => {
+                sync_timers = true;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown argument \"{}\"", arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let [rom_path, trace_path] = <[PathBuf; 2]>::try_from(paths).map_err(|_| USAGE)?;
+
+    let trace = std::fs::read_to_string(&trace_path)
+        .map_err(|error| format!("cannot read {}: {}", trace_path.display(), error))?;
+
+    let mut chip8 = ChipContext::reset();
+    chip8.load_program(&rom_path)?;
+    chip8.quirks = quirks;
+
+    let symbols = SymbolMap::empty();
+    let mut context: VecDeque<String> = VecDeque::with_capacity(CONTEXT_LINES);
+    let mut first_cycle: Option<u64> = None;
+    let mut executed: u64 = 0;
+    // Register written by the last Cxkk, its random value is taken from the reference
+    let mut random_register: Option<usize> = None;
+
+    for (line_number, line) in trace.lines().enumerate() {
+        let expected = match TraceRecord::parse(line) {
+            None => continue,
+            Some(record) => record.map_err(|error| format!("{}:{}: {}", trace_path.display(), line_number + 1, error))?,
+        };
+        let cycle = match expected.cycle.checked_sub(*first_cycle.get_or_insert(expected.cycle)) {
+            Some(cycle) if cycle >= executed => cycle,
+            _ => return Err(format!("{}:{}: cycle {} goes backwards", trace_path.display(), line_number + 1, expected.cycle)),
+        };
+
+        // Traces filtered by address or opcode skip cycles, run through them with the last keys held
+        while executed < cycle {
+            if executed.is_multiple_of(instructions_per_frame) {
+                chip8.update_timers();
+                chip8.vblank_ready = true;
+            }
+            chip8.exec_opcode();
+            executed += 1;
+        }
+
+        if executed.is_multiple_of(instructions_per_frame) {
+            chip8.update_timers();
+            chip8.vblank_ready = true;
+        }
+        for (key, pressed) in chip8.keyboard_keys.iter_mut().enumerate() {
+            *pressed = expected.keys & (1 << key) != 0;
+        }
+        if let Some(register) = random_register.take() {
+            chip8.registers[register] = expected.registers[register];
+        }
+        if sync_timers {
+            chip8.delay_reg = expected.delay;
+            chip8.sound_reg = expected.sound;
+        }
+
+        let mut actual = TraceRecord::capture(&chip8, executed);
+        let mut differences = compare_state(&expected, &actual, !sync_timers);
+
+        if differences.is_empty() {
+            if get_byte_0xF000(actual.opcode) == 0xC {
+                random_register = Some(get_byte_0x0F00(actual.opcode) as usize);
+            }
+            chip8.exec_opcode();
+            executed += 1;
+            actual.capture_writes(&chip8);
+            for (address, value) in &expected.writes {
+                let value_in_memory = chip8.memory[*address as usize & 0xFFF];
+                if value_in_memory != *value {
+                    differences.push(format!("memory {:03X}: expected {:02X}, got {:02X}", address, value, value_in_memory));
+                }
+            }
+        }
+
+        if !differences.is_empty() {
+            println!("first divergence at cycle {} ({}:{})", expected.cycle, trace_path.display(), line_number + 1);
+            for matching in &context {
+                println!("    {}", matching);
+            }
+            println!("  expected: {}", expected.format(&symbols));
+            println!("  actual:   {}", actual.format(&symbols));
+            for difference in differences {
+                println!("  {}", difference);
+            }
+            return Ok(());
+        }
+
+        if context.len() == CONTEXT_LINES {
+            context.pop_front();
+        }
+        context.push_back(actual.format(&symbols));
+    }
+
+    println!("no divergence in {} instructions", executed);
+    Ok(())
+}
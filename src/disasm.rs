@@ -0,0 +1,81 @@
+use crate::{get_byte_0x000F, get_byte_0x00F0, get_byte_0x0F00, get_byte_0xF000, get_bytes_0x00FF, get_bytes_0x0FFF};
+use crate::symbols::SymbolMap;
+
+/* Mnemonic of opcode in the notation used by the comments in exec_opcode,
+ * e.g. "ld V3, 0x0A" or "drw V0, V1, 5"
+ */
+pub fn disassemble(opcode: u16) -> String {
+    let x = get_byte_0x0F00(opcode);
+    let y = get_byte_0x00F0(opcode);
+    let n = get_byte_0x000F(opcode);
+    let kk = get_bytes_0x00FF(opcode);
+    let nnn = get_bytes_0x0FFF(opcode);
+
+    match get_byte_0xF000(opcode) {
+        0x0 => match opcode {
+            0x00E0 => String::from("cls"),
+            0x00EE => String::from("ret"),
+            _ => format!("sys 0x{:03X}", nnn),
+        },
+        0x1 => format!("jp 0x{:03X}", nnn),
+        0x2 => format!("call 0x{:03X}", nnn),
+        0x3 => format!("se V{:X}, 0x{:02X}", x, kk),
+        0x4 => format!("sne V{:X}, 0x{:02X}", x, kk),
+        0x5 if n == 0 => format!("se V{:X}, V{:X}", x, y),
+        0x6 => format!("ld V{:X}, 0x{:02X}", x, kk),
+        0x7 => format!("add V{:X}, 0x{:02X}", x, kk),
+        0x8 => match n {
+            0x0 => format!("ld V{:X}, V{:X}", x, y),
+            0x1 => format!("or V{:X}, V{:X}", x, y),
+            0x2 => format!("and V{:X}, V{:X}", x, y),
+            0x3 => format!("xor V{:X}, V{:X}", x, y),
+            0x4 => format!("add V{:X}, V{:X}", x, y),
+            0x5 => format!("sub V{:X}, V{:X}", x, y),
+            0x6 => format!("shr V{:X}, V{:X}", x, y),
+            0x7 => format!("subn V{:X}, V{:X}", x, y),
+            0xE => format!("shl V{:X}, V{:X}", x, y),
+            _ => format!("db 0x{:04X}", opcode),
+        },
+        0x9 if n == 0 => format!("sne V{:X}, V{:X}", x, y),
+        0xA => format!("ld i, 0x{:03X}", nnn),
+        0xB => format!("jp V0, 0x{:03X}", nnn),
+        0xC => format!("rnd V{:X}, 0x{:02X}", x, kk),
+        0xD => format!("drw V{:X}, V{:X}, {}", x, y, n),
+        0xE => match kk {
+            0x9E => format!("skp V{:X}", x),
+            0xA1 => format!("sknp V{:X}", x),
+            _ => format!("db 0x{:04X}", opcode),
+        },
+        0xF => match kk {
+            0x07 => format!("ld V{:X}, dt", x),
+            0x0A => format!("ld V{:X}, k", x),
+            0x15 => format!("ld dt, V{:X}", x),
+            0x18 => format!("ld st, V{:X}", x),
+            0x1E => format!("add i, V{:X}", x),
+            0x29 => format!("ld f, V{:X}", x),
+            0x33 => format!("ld b, V{:X}", x),
+            0x55 => format!("ld [i], V{:X}", x),
+            0x65 => format!("ld V{:X}, [i]", x),
+            _ => format!("db 0x{:04X}", opcode),
+        },
+        _ => format!("db 0x{:04X}", opcode),
+    }
+}
+
+/* Like disassemble, with the address of jp, call and ld i replaced by its
+ * label when there is one
+ */
+pub fn disassemble_labeled(opcode: u16, symbols: &SymbolMap) -> String {
+    let nnn = get_bytes_0x0FFF(opcode);
+    let label = match symbols.label(nnn) {
+        Some(label) => label,
+        None => return disassemble(opcode),
+    };
+    match get_byte_0xF000(opcode) {
+        0x1 => format!("jp {}", label),
+        0x2 => format!("call {}", label),
+        0xA => format!("ld i, {}", label),
+        0xB => format!("jp V0, {}", label),
+        _ => disassemble(opcode),
+    }
+}
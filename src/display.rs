@@ -0,0 +1,230 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+use crate::EmulatorConfig;
+use crate::palette::Palette;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScalingMode {
+    // Largest whole multiple of 64x32 that fits, so every CHIP-8 pixel has the same size
+    Integer,
+    // Fill the window as far as the 2:1 aspect ratio allows
+    Fit,
+    // Fill the whole window, ignoring the aspect ratio
+    Stretch,
+}
+
+impl ScalingMode {
+    pub fn from_name(name: &str) -> Option<ScalingMode> {
+        match name {
+            "integer" => Some(ScalingMode::Integer),
+            "fit" => Some(ScalingMode::Fit),
+            "stretch" => Some(ScalingMode::Stretch),
+            _ => None,
+        }
+    }
+}
+
+/* Display-only filters against the flicker caused by XOR sprite drawing,
+ * the framebuffer in ChipContext is never touched
+ */
+#[derive(Clone, Copy, PartialEq)]
+pub enum DisplayFilter {
+    None,
+    // Lit pixels fade out over several frames, strength is the share of intensity kept per frame
+    Fade { strength: f32 },
+    // A pixel is shown if it was lit in this frame or the previous one
+    Blend,
+}
+
+impl DisplayFilter {
+    pub fn from_name(name: &str, strength: f32) -> Option<DisplayFilter> {
+        match name {
+            "none" => Some(DisplayFilter::None),
+            "fade" => Some(DisplayFilter::Fade { strength: strength.clamp(0.0, 0.99) }),
+            "blend" => Some(DisplayFilter::Blend),
+            _ => None,
+        }
+    }
+}
+
+pub struct Renderer<'a> {
+    texture: Texture<'a>,
+    pixels: Vec<u8>,
+    scaling_mode: ScalingMode,
+    filter: DisplayFilter,
+    palettes: Vec<Palette>,
+    palette_index: usize,
+    intensity: [[f32; 32]; 64],
+    previous_frame: [[u8; 32]; 64],
+    needs_redraw: bool,
+}
+
+impl<'a> Renderer<'a> {
+    const BYTES_PER_PIXEL: usize = 3;
+
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>, scaling_mode: ScalingMode, filter: DisplayFilter, palette: Palette) -> Renderer<'a> {
+        let mut palettes = Palette::built_in();
+        let palette_index = match palettes.iter().position(|built_in| built_in.name == palette.name) {
+            Some(index) => index,
+            None => {
+                palettes.push(palette);
+                palettes.len() - 1
+            }
+        };
+
+        let texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                EmulatorConfig::SCREEN_WIDTH,
+                EmulatorConfig::SCREEN_HEIGHT)
+            .unwrap();
+
+        Renderer {
+            texture,
+            pixels: vec![0; (EmulatorConfig::SCREEN_WIDTH * EmulatorConfig::SCREEN_HEIGHT) as usize * Self::BYTES_PER_PIXEL],
+            scaling_mode,
+            filter,
+            palettes,
+            palette_index,
+            intensity: [[0.0; 32]; 64],
+            previous_frame: [[0; 32]; 64],
+            needs_redraw: false,
+        }
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palettes[self.palette_index]
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        match self.palettes.iter().position(|known| known.name == palette.name) {
+            Some(index) => self.palette_index = index,
+            None => {
+                self.palettes.push(palette);
+                self.palette_index = self.palettes.len() - 1;
+            }
+        }
+    }
+
+    pub fn cycle_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+    }
+
+    /* True while the filter still changes the picture without the framebuffer changing,
+     * e.g. pixels fading out
+     */
+    pub fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    /* Area of the window the framebuffer is copied to, centered with
+     * letterbox/pillarbox bars around it unless stretching
+     */
+    fn destination_rect(&self, output_width: u32, output_height: u32) -> Rect {
+        let (width, height) = match self.scaling_mode {
+            ScalingMode::Integer => {
+                let scale = (output_width / EmulatorConfig::SCREEN_WIDTH)
+                    .min(output_height / EmulatorConfig::SCREEN_HEIGHT)
+                    .max(1);
+                (EmulatorConfig::SCREEN_WIDTH * scale, EmulatorConfig::SCREEN_HEIGHT * scale)
+            }
+            ScalingMode::Fit => {
+                let scale = (output_width as f32 / EmulatorConfig::SCREEN_WIDTH as f32)
+                    .min(output_height as f32 / EmulatorConfig::SCREEN_HEIGHT as f32);
+                (
+                    ((EmulatorConfig::SCREEN_WIDTH as f32 * scale) as u32).max(1),
+                    ((EmulatorConfig::SCREEN_HEIGHT as f32 * scale) as u32).max(1),
+                )
+            }
+            ScalingMode::Stretch => (output_width.max(1), output_height.max(1)),
+        };
+
+        Rect::new(
+            (output_width as i32 - width as i32) / 2,
+            (output_height as i32 - height as i32) / 2,
+            width,
+            height
+            )
+    }
+
+    fn blend_colors(background: Color, foreground: Color, intensity: f32) -> Color {
+        let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * intensity) as u8;
+        Color::RGB(
+            mix(background.r, foreground.r),
+            mix(background.g, foreground.g),
+            mix(background.b, foreground.b),
+            )
+    }
+
+    /* Brightness of a pixel in 0.0..=1.0 after applying the filter */
+    fn filtered_intensity(&mut self, x: usize, y: usize, pixel: u8) -> f32 {
+        match self.filter {
+            DisplayFilter::None => pixel.min(1) as f32,
+            DisplayFilter::Fade { strength } => {
+                let intensity = if pixel != 0 {
+                    1.0
+                }
+                else {
+                    let faded = self.intensity[x][y] * strength;
+                    if faded < 1.0 / 255.0 { 0.0 } else { faded }
+                };
+                if pixel == 0 && intensity > 0.0 {
+                    self.needs_redraw = true;
+                }
+                self.intensity[x][y] = intensity;
+                intensity
+            }
+            DisplayFilter::Blend => {
+                let previous = self.previous_frame[x][y];
+                self.previous_frame[x][y] = pixel;
+                if previous != pixel {
+                    self.needs_redraw = true;
+                }
+                (pixel | previous).min(1) as f32
+            }
+        }
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        let offset = (y * EmulatorConfig::SCREEN_WIDTH as usize + x) * Self::BYTES_PER_PIXEL;
+        self.pixels[offset] = color.r;
+        self.pixels[offset + 1] = color.g;
+        self.pixels[offset + 2] = color.b;
+    }
+
+    /* Draws the framebuffer into the canvas, presenting is left to the caller
+     * so overlays can be drawn on top
+     */
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, frame_buffer: &[[u8; 32]; 64]){
+        self.needs_redraw = false;
+        for (x, column) in frame_buffer.iter().enumerate() {
+            for (y, pixel) in column.iter().enumerate() {
+                let colors = self.palette().colors;
+                let intensity = self.filtered_intensity(x, y, *pixel);
+                if *pixel != 0 {
+                    self.put_pixel(x, y, colors[*pixel as usize & 0x3]);
+                }
+                else if intensity >= 1.0 {
+                    self.put_pixel(x, y, colors[1]);
+                }
+                else if intensity <= 0.0 {
+                    self.put_pixel(x, y, colors[0]);
+                }
+                else {
+                    self.put_pixel(x, y, Self::blend_colors(colors[0], colors[1], intensity));
+                }
+            }
+        }
+
+        let pitch = EmulatorConfig::SCREEN_WIDTH as usize * Self::BYTES_PER_PIXEL;
+        let _ = self.texture.update(None, &self.pixels, pitch);
+
+        let (output_width, output_height) = canvas.output_size().unwrap();
+        canvas.set_draw_color(EmulatorConfig::LETTERBOX_COLOR);
+        canvas.clear();
+        let _ = canvas.copy(&self.texture, None, self.destination_rect(output_width, output_height));
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::ChipContext;
+
+/* What the main loop has to do after a poll */
+#[derive(Clone, Copy, PartialEq)]
+pub enum GdbCommand {
+    None,
+    Halt,
+    Continue,
+    Step,
+    Kill,
+}
+
+/* Register numbers of the g/G/p/P packets, V0-VF are 0-15 */
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_DT: usize = 19;
+const REGISTER_ST: usize = 20;
+const REGISTER_COUNT: usize = 21;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/* GDB remote serial protocol server for a single client. Registers are sent
+ * little endian, memory addresses are the 4K CHIP-8 address space.
+ */
+pub struct GdbServer {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    input: Vec<u8>,
+    breakpoints: HashSet<u16>,
+    // The client is waiting for a stop reply
+    running: bool,
+    // Address of the breakpoint execution is resumed from, it must not stop there again right away
+    resume_from: Option<u16>,
+    // Set when a breakpoint stopped execution, until the main loop picked it up
+    breakpoint_hit: bool,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn decode_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn register_bytes(chip8: &ChipContext, register: usize) -> Vec<u8> {
+    match register {
+        0..=15 => vec![chip8.registers[register]],
+        REGISTER_I => chip8.I.to_le_bytes().to_vec(),
+        REGISTER_PC => chip8.PC.to_le_bytes().to_vec(),
+        REGISTER_SP => vec![chip8.SP],
+        REGISTER_DT => vec![chip8.delay_reg],
+        REGISTER_ST => vec![chip8.sound_reg],
+        _ => Vec::new(),
+    }
+}
+
+/* False if the value is out of range for the register, a PC past the last
+ * fetchable address or SP deeper than the stack would crash the next step
+ */
+fn set_register(chip8: &mut ChipContext, register: usize, bytes: &[u8]) -> bool {
+    let word = u16::from_le_bytes([bytes[0], *bytes.get(1).unwrap_or(&0)]);
+    match register {
+        0..=15 => chip8.registers[register] = bytes[0],
+        REGISTER_I => chip8.I = word & 0xFFF,
+        REGISTER_PC if word <= ChipContext::MAX_PC => chip8.PC = word,
+        REGISTER_SP if bytes[0] as usize <= chip8.stack.len() => chip8.SP = bytes[0],
+        REGISTER_DT => chip8.delay_reg = bytes[0],
+        REGISTER_ST => chip8.sound_reg = bytes[0],
+        _ => return false,
+    }
+    true
+}
+
+fn register_size(register: usize) -> usize {
+    match register {
+        REGISTER_I | REGISTER_PC => 2,
+        _ => 1,
+    }
+}
+
+impl GdbServer {
+    const STOP_REPLY: &'static str = "S05";
+    const ERROR_REPLY: &'static str = "E01";
+
+    pub fn listen(port: u16) -> Result<GdbServer, String> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|error| format!("cannot listen on port {}: {}", port, error))?;
+        listener.set_nonblocking(true).map_err(|error| error.to_string())?;
+        println!("waiting for gdb on 127.0.0.1:{}", port);
+        Ok(GdbServer {
+            listener,
+            client: None,
+            input: Vec::new(),
+            breakpoints: HashSet::new(),
+            running: false,
+            resume_from: None,
+            breakpoint_hit: false,
+        })
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        if let Some(client) = self.client.as_mut() {
+            let _ = client.write_all(packet.as_bytes());
+        }
+    }
+
+    /* Call before every instruction, true if execution has to stop at PC */
+    pub fn should_stop(&mut self, chip8: &ChipContext) -> bool {
+        if self.resume_from.take() == Some(chip8.PC) || !self.breakpoints.contains(&chip8.PC) {
+            return false;
+        }
+        if self.running {
+            self.running = false;
+            self.send_packet(Self::STOP_REPLY);
+        }
+        self.breakpoint_hit = true;
+        true
+    }
+
+    pub fn breakpoint_hit(&self) -> bool {
+        self.breakpoint_hit
+    }
+
+    pub fn take_breakpoint_hit(&mut self) -> bool {
+        std::mem::take(&mut self.breakpoint_hit)
+    }
+
+    /* Tells a waiting client that execution stopped for another reason than its breakpoints */
+    pub fn report_stop(&mut self) {
+        if self.running {
+            self.running = false;
+            self.send_packet(Self::STOP_REPLY);
+        }
+    }
+
+    /* Call after executing the instruction of a Step command */
+    pub fn step_done(&mut self) {
+        self.send_packet(Self::STOP_REPLY);
+    }
+
+    fn disconnect(&mut self) {
+        self.client = None;
+        self.input.clear();
+        self.breakpoints.clear();
+        self.running = false;
+        println!("gdb disconnected");
+    }
+
+    /* Accepts a client, reads its packets and answers them, returns what the
+     * main loop has to do for the last packet that needed it
+     */
+    pub fn poll(&mut self, chip8: &mut ChipContext) -> GdbCommand {
+        if self.client.is_none() {
+            return match self.listener.accept() {
+                Ok((client, address)) => {
+                    let _ = client.set_nonblocking(true);
+                    let _ = client.set_nodelay(true);
+                    println!("gdb connected from {}", address);
+                    self.client = Some(client);
+                    GdbCommand::Halt
+                }
+                Err(_) => GdbCommand::None,
+            };
+        }
+
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = self.client.as_mut().unwrap().read(&mut buffer);
+            match read {
+                Ok(0) => {
+                    self.disconnect();
+                    return GdbCommand::Continue;
+                }
+                Ok(length) => self.input.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.disconnect();
+                    return GdbCommand::Continue;
+                }
+            }
+        }
+
+        let mut command = GdbCommand::None;
+        while let Some(start) = self.input.iter().position(|byte| *byte == b'$' || *byte == 0x03) {
+            // Ctrl-C from the client
+            if self.input[start] == 0x03 {
+                self.input.drain(..=start);
+                if self.running {
+                    self.running = false;
+                    self.send_packet(Self::STOP_REPLY);
+                }
+                command = GdbCommand::Halt;
+                continue;
+            }
+
+            let Some(end) = self.input[start..].iter().position(|byte| *byte == b'#').map(|end| start + end) else {
+                break;
+            };
+            if self.input.len() < end + 3 {
+                break;
+            }
+            let packet: Vec<u8> = self.input[start + 1..end].to_vec();
+            let expected = std::str::from_utf8(&self.input[end + 1..end + 3]).ok().and_then(parse_hex);
+            self.input.drain(..end + 3);
+
+            if expected != Some(checksum(&packet) as usize) {
+                if let Some(client) = self.client.as_mut() {
+                    let _ = client.write_all(b"-");
+                }
+                continue;
+            }
+            if let Some(client) = self.client.as_mut() {
+                let _ = client.write_all(b"+");
+            }
+
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            match self.handle_packet(&packet, chip8) {
+                GdbCommand::None => {}
+                other => command = other,
+            }
+            if self.client.is_none() {
+                break;
+            }
+        }
+        command
+    }
+
+    fn handle_packet(&mut self, packet: &str, chip8: &mut ChipContext) -> GdbCommand {
+        let (kind, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match kind {
+            "?" => String::from(Self::STOP_REPLY),
+
+            "g" => (0..REGISTER_COUNT).map(|register| encode_hex_bytes(&register_bytes(chip8, register))).collect(),
+
+            "G" => match decode_hex_bytes(arguments) {
+                Some(bytes) => {
+                    let mut offset = 0;
+                    let mut valid = true;
+                    for register in 0..REGISTER_COUNT {
+                        let size = register_size(register);
+                        if offset + size > bytes.len() {
+                            break;
+                        }
+                        valid &= set_register(chip8, register, &bytes[offset..offset + size]);
+                        offset += size;
+                    }
+                    String::from(if valid { "OK" } else { Self::ERROR_REPLY })
+                }
+                None => String::from(Self::ERROR_REPLY),
+            },
+
+            "p" => match parse_hex(arguments).filter(|register| *register < REGISTER_COUNT) {
+                Some(register) => encode_hex_bytes(&register_bytes(chip8, register)),
+                None => String::from(Self::ERROR_REPLY),
+            },
+
+            "P" => {
+                let parsed = arguments.split_once('=').and_then(|(register, value)| {
+                    Some((parse_hex(register).filter(|register| *register < REGISTER_COUNT)?, decode_hex_bytes(value)?))
+                });
+                match parsed {
+                    Some((register, bytes)) if !bytes.is_empty() && set_register(chip8, register, &bytes) => String::from("OK"),
+                    _ => String::from(Self::ERROR_REPLY),
+                }
+            }
+
+            "m" => {
+                let range = arguments.split_once(',').and_then(|(address, length)| Some((parse_hex(address)?, parse_hex(length)?)));
+                match range {
+                    Some((address, length)) if address.checked_add(length).is_some_and(|end| end <= chip8.memory.len()) => {
+                        encode_hex_bytes(&chip8.memory[address..address + length])
+                    }
+                    _ => String::from(Self::ERROR_REPLY),
+                }
+            }
+
+            "M" => {
+                let parsed = arguments.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = range.split_once(',')?;
+                    Some((parse_hex(address)?, parse_hex(length)?, decode_hex_bytes(data)?))
+                });
+                match parsed {
+                    Some((address, length, bytes)) if bytes.len() == length
+                        && address.checked_add(length).is_some_and(|end| end <= chip8.memory.len()) => {
+                        chip8.memory[address..address + length].copy_from_slice(&bytes);
+                        String::from("OK")
+                    }
+                    _ => String::from(Self::ERROR_REPLY),
+                }
+            }
+
+            "c" | "s" => match parse_hex(arguments) {
+                Some(address) if address > ChipContext::MAX_PC as usize => String::from(Self::ERROR_REPLY),
+                address => {
+                    if let Some(address) = address {
+                        chip8.PC = address as u16;
+                    }
+                    self.resume_from = Some(chip8.PC);
+                    if kind == "s" {
+                        return GdbCommand::Step;
+                    }
+                    self.running = true;
+                    return GdbCommand::Continue;
+                }
+            },
+
+            "Z" | "z" => {
+                let mut fields = arguments.split(',');
+                let breakpoint_type = fields.next();
+                let address = fields.next().and_then(parse_hex);
+                match (breakpoint_type, address) {
+                    // Software and hardware breakpoints work the same here
+                    (Some("0" | "1"), Some(address)) if address >= 0x1000 => String::from(Self::ERROR_REPLY),
+                    (Some("0" | "1"), Some(address)) => {
+                        if kind == "Z" {
+                            self.breakpoints.insert(address as u16);
+                        }
+                        else {
+                            self.breakpoints.remove(&(address as u16));
+                        }
+                        String::from("OK")
+                    }
+                    _ => String::new(),
+                }
+            }
+
+            "q" => {
+                if arguments.starts_with("Supported") {
+                    String::from("PacketSize=1000;qXfer:features:read+")
+                }
+                else if let Some(range) = arguments.strip_prefix("Xfer:features:read:target.xml:") {
+                    match range.split_once(',').and_then(|(offset, length)| Some((parse_hex(offset)?, parse_hex(length)?))) {
+                        Some((offset, _)) if offset >= TARGET_XML.len() => String::from("l"),
+                        Some((offset, length)) => {
+                            let end = offset.saturating_add(length).min(TARGET_XML.len());
+                            let marker = if end == TARGET_XML.len() { "l" } else { "m" };
+                            format!("{}{}", marker, &TARGET_XML[offset..end])
+                        }
+                        None => String::from(Self::ERROR_REPLY),
+                    }
+                }
+                else if arguments == "Attached" {
+                    String::from("1")
+                }
+                else {
+                    String::new()
+                }
+            }
+
+            "H" => String::from("OK"),
+
+            "k" => {
+                self.disconnect();
+                return GdbCommand::Kill;
+            }
+
+            "D" => {
+                self.send_packet("OK");
+                self.disconnect();
+                return GdbCommand::Continue;
+            }
+
+            _ => String::new(),
+        };
+        self.send_packet(&reply);
+        GdbCommand::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn connect() -> (GdbServer, TcpStream, ChipContext) {
+        let mut server = GdbServer::listen(0).unwrap();
+        let client = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let mut chip8 = ChipContext::reset();
+        let start = Instant::now();
+        while server.client.is_none() && start.elapsed() < Duration::from_secs(2) {
+            server.poll(&mut chip8);
+        }
+        assert!(server.client.is_some());
+        (server, client, chip8)
+    }
+
+    /* Sends raw bytes and collects everything the server answers */
+    fn exchange(server: &mut GdbServer, client: &mut TcpStream, chip8: &mut ChipContext, data: &[u8]) -> String {
+        client.write_all(data).unwrap();
+        let mut reply = Vec::new();
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(300) {
+            server.poll(chip8);
+            let mut buffer = [0u8; 256];
+            if let Ok(length) = client.read(&mut buffer) {
+                reply.extend_from_slice(&buffer[..length]);
+            }
+            if reply.ends_with(b"-") || reply.iter().rev().nth(2) == Some(&b'#') {
+                break;
+            }
+        }
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn checksum_is_the_byte_sum_modulo_256() {
+        assert_eq!(checksum(b""), 0);
+        assert_eq!(checksum(b"?"), 0x3F);
+        assert_eq!(checksum(b"OK"), 0x9A);
+        assert_eq!(checksum(&[0xFF, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn hex_bytes_round_trip() {
+        assert_eq!(encode_hex_bytes(&[0x00, 0xAB, 0x12]), "00ab12");
+        assert_eq!(decode_hex_bytes("00ab12"), Some(vec![0x00, 0xAB, 0x12]));
+        assert_eq!(decode_hex_bytes("abc"), None);
+        assert_eq!(decode_hex_bytes("zz"), None);
+    }
+
+    #[test]
+    fn packets_are_acknowledged_and_framed() {
+        let (mut server, mut client, mut chip8) = connect();
+        assert_eq!(exchange(&mut server, &mut client, &mut chip8, b"$?#3f"), "+$S05#b8");
+        chip8.memory[0x200] = 0x12;
+        chip8.memory[0x201] = 0x34;
+        assert_eq!(exchange(&mut server, &mut client, &mut chip8, b"$m200,2#5d"), "+$1234#ca");
+    }
+
+    #[test]
+    fn bad_checksums_are_rejected() {
+        let (mut server, mut client, mut chip8) = connect();
+        assert_eq!(exchange(&mut server, &mut client, &mut chip8, b"$?#00"), "-");
+        // A packet split across reads is answered once it is complete
+        client.write_all(b"$?#").unwrap();
+        assert_eq!(exchange(&mut server, &mut client, &mut chip8, b"3f"), "+$S05#b8");
+    }
+}
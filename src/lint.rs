@@ -0,0 +1,226 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::disasm;
+use crate::rom::{self, Platform};
+use crate::{get_byte_0x000F, get_byte_0x0F00, get_byte_0xF000, get_bytes_0x00FF, get_bytes_0x0FFF, ChipContext};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+/* Quirks an instruction behaves differently under */
+#[derive(Clone, Copy, PartialEq, PartialOrd, Eq, Ord)]
+enum Quirk {
+    Shift,
+    VfReset,
+    Jump,
+    LoadStore,
+}
+
+impl Quirk {
+    fn name(&self) -> &'static str {
+        match self {
+            Quirk::Shift => "shift quirk",
+            Quirk::VfReset => "VF reset quirk",
+            Quirk::Jump => "jump quirk",
+            Quirk::LoadStore => "load/store quirk",
+        }
+    }
+}
+
+/* Checks every instruction the walk in rom::reachable_code reaches. I is
+ * known there while it is set by Annn, so the memory ranges of Dxyn and
+ * Fx55/Fx65 can be checked.
+ */
+struct Linter {
+    chip8: ChipContext,
+    program_end: u16,
+    findings: Vec<(u16, Severity, String)>,
+    platform: Platform,
+    sensitive: BTreeSet<Quirk>,
+    // Addresses stored to by Fx33/Fx55 with a known I, with the address of the store
+    written: BTreeMap<u16, u16>,
+}
+
+impl Linter {
+    fn report(&mut self, address: u16, severity: Severity, message: String) {
+        self.findings.push((address, severity, message));
+    }
+
+    fn check_target(&mut self, address: u16, target: u16) {
+        if target < ChipContext::PROGRAM_START {
+            self.report(address, Severity::Error, format!("target {:03X} is below the program start at 200", target));
+        }
+        // Code past the ROM is fine if the program writes it there first
+        else if target >= self.program_end {
+            self.report(address, Severity::Warning, format!("target {:03X} is past the end of the ROM at {:03X}", target, self.program_end));
+        }
+        else if !target.is_multiple_of(2) {
+            self.report(address, Severity::Warning, format!("target {:03X} is odd, code usually starts at even addresses", target));
+        }
+    }
+
+    fn check_range(&mut self, address: u16, i: Option<u16>, length: u16, instruction: &str) {
+        if let Some(i) = i {
+            if i as usize + length as usize > 0x1000 {
+                self.report(address, Severity::Error,
+                    format!("{} with I = {:03X} accesses {} bytes past the end of memory", instruction, i, i as usize + length as usize - 0x1000));
+            }
+        }
+    }
+
+    fn check(&mut self, address: u16, i: Option<u16>) {
+        if address < ChipContext::PROGRAM_START {
+            self.report(address, Severity::Error, format!("execution reaches {:03X}, below the program start", address));
+            return;
+        }
+        if address >= self.program_end {
+            self.report(address, Severity::Warning, format!("execution reaches {:03X}, past the end of the ROM", address));
+            return;
+        }
+
+        let opcode = self.chip8.opcode_at(address);
+        let x = get_byte_0x0F00(opcode);
+        let nnn = get_bytes_0x0FFF(opcode);
+
+        if !rom::is_valid_opcode(opcode) {
+            match self.written.get(&address).or_else(|| self.written.get(&(address + 1))) {
+                Some(writer) => self.report(address, Severity::Warning,
+                    format!("{:04X} is not a valid instruction, the code is written at runtime by {:03X}", opcode, writer)),
+                None => self.report(address, Severity::Error, format!("{:04X} is not a valid instruction", opcode)),
+            }
+            return;
+        }
+        if rom::is_xo_chip_opcode(opcode) {
+            self.platform = Platform::XoChip;
+            self.report(address, Severity::Info, format!("{:04X} is an XO-CHIP instruction", opcode));
+        }
+        else if rom::is_super_chip_opcode(opcode) {
+            if self.platform == Platform::Chip8 {
+                self.platform = Platform::SuperChip;
+            }
+            self.report(address, Severity::Info, format!("{:04X} is a SUPER-CHIP instruction", opcode));
+        }
+
+        match get_byte_0xF000(opcode) {
+            0x1 => {
+                self.check_target(address, nnn);
+                if nnn == address {
+                    self.report(address, Severity::Info, String::from("jumps to itself, the program halts here"));
+                }
+            }
+            0x2 => self.check_target(address, nnn),
+            0xB => {
+                self.report(address, Severity::Warning, String::from("computed jump, its targets are not checked"));
+                self.report(address, Severity::Info, String::from("Bnnn behaves differently with the jump quirk (Bxnn on SUPER-CHIP)"));
+                self.sensitive.insert(Quirk::Jump);
+            }
+            0x8 => match get_byte_0x000F(opcode) {
+                0x6 | 0xE => {
+                    self.report(address, Severity::Info, String::from("shift depends on the shift quirk (Vx or Vy is shifted)"));
+                    self.sensitive.insert(Quirk::Shift);
+                }
+                0x1..=0x3 => {
+                    self.report(address, Severity::Info, String::from("logic op depends on the VF reset quirk"));
+                    self.sensitive.insert(Quirk::VfReset);
+                }
+                _ => {}
+            },
+            0xD => {
+                // Dxy0 draws a 16x16 SCHIP sprite of 32 bytes
+                let n = get_byte_0x000F(opcode);
+                let length = if n == 0 { 32 } else { n };
+                self.check_range(address, i, length, "drw");
+            }
+            0xF => match get_bytes_0x00FF(opcode) {
+                0x55 | 0x65 => {
+                    let instruction = if get_bytes_0x00FF(opcode) == 0x55 { "ld [i], Vx" } else { "ld Vx, [i]" };
+                    self.check_range(address, i, x + 1, instruction);
+                    self.report(address, Severity::Info,
+                        format!("{} depends on the load/store quirk (I is incremented or not)", instruction));
+                    self.sensitive.insert(Quirk::LoadStore);
+                }
+                0x33 => self.check_range(address, i, 3, "ld b, Vx"),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/* "lint ROM", statically checks all reachable code of a ROM */
+pub fn run(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let rom_path = match (args.next(), args.next()) {
+        (Some(path), None) if !path.starts_with("--") => PathBuf::from(path),
+        _ => return Err(String::from("usage: lint ROM")),
+    };
+
+    let mut chip8 = ChipContext::reset();
+    chip8.load_program(&rom_path)?;
+
+    let reached = rom::reachable_code(&chip8);
+    let mut written = BTreeMap::new();
+    for (address, i) in &reached {
+        let opcode = chip8.opcode_at(*address);
+        let length = match (get_byte_0xF000(opcode), get_bytes_0x00FF(opcode)) {
+            (0xF, 0x33) => 3,
+            (0xF, 0x55) => get_byte_0x0F00(opcode) + 1,
+            _ => 0,
+        };
+        if let Some(i) = i {
+            for offset in 0..length {
+                written.insert((i + offset) & 0xFFF, *address);
+            }
+        }
+    }
+    let mut linter = Linter {
+        program_end: ChipContext::PROGRAM_START + chip8.program_size as u16,
+        chip8,
+        findings: Vec::new(),
+        platform: Platform::Chip8,
+        sensitive: BTreeSet::new(),
+        written,
+    };
+    for (address, i) in &reached {
+        linter.check(*address, *i);
+    }
+
+    linter.findings.sort_by_key(|(address, severity, _)| (*address, *severity));
+    for (address, severity, message) in &linter.findings {
+        let opcode = linter.chip8.opcode_at(*address);
+        println!("{:03X}  {:04X}  {:<18} {}: {}", address, opcode, disasm::disassemble(opcode), severity.name(), message);
+    }
+
+    let count = |wanted: Severity| linter.findings.iter().filter(|(_, severity, _)| *severity == wanted).count();
+    let reachable = reached.keys().filter(|address| **address >= ChipContext::PROGRAM_START && **address < linter.program_end).count();
+    println!();
+    println!("{} reachable instructions, {} errors, {} warnings", reachable, count(Severity::Error), count(Severity::Warning));
+    println!("needs {}", linter.platform.name());
+
+    let sensitive: Vec<&str> = linter.sensitive.iter().map(Quirk::name).collect();
+    if sensitive.is_empty() {
+        println!("no quirk-sensitive instructions");
+    }
+    else {
+        println!("sensitive to: {}", sensitive.join(", "));
+    }
+
+    match count(Severity::Error) {
+        0 => Ok(()),
+        errors => Err(format!("{} errors", errors)),
+    }
+}
@@ -1,25 +1,107 @@
 #![allow(non_snake_case)]
 
+mod bench;
+mod callstack;
+mod capture;
+mod cheats;
+mod console;
+mod difftrace;
+mod disasm;
+mod display;
+mod font;
+mod gdb;
+mod lint;
+mod memview;
+mod menu;
+mod origins;
+mod osd;
+mod pacing;
+mod palette;
+mod profiler;
+mod rom;
+mod romdb;
+mod script;
+mod source;
+mod symbols;
+mod timing;
+mod trace;
+mod watch;
+
 use sdl2::pixels::Color;
-use sdl2::event::{Event, EventPollIterator};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::rect::Rect;
 use sdl2::render::Canvas;
 
-use std::time::Instant;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use callstack::StackChecker;
+use capture::Recorder;
+use cheats::CheatEngine;
+use console::Console;
+use display::{DisplayFilter, Renderer, ScalingMode};
+use gdb::{GdbCommand, GdbServer};
+use memview::MemoryViewer;
+use menu::{MenuAction, RomMenu};
+use origins::MemoryOrigins;
+use osd::Osd;
+use pacing::{FramePacer, PacingMode};
+use palette::Palette;
+use profiler::Profiler;
+use rom::Platform;
+use romdb::{RomDatabase, RomSettings};
+use script::Script;
+use symbols::SymbolMap;
+use timing::TimingModel;
+use trace::Tracer;
+use watch::FileWatcher;
 
 struct EmulatorConfig;
 impl EmulatorConfig {
     const SCREEN_WIDTH: u32 = 64;
     const SCREEN_HEIGHT: u32 = 32;
     const SCREEN_SCALE: u32 = 20;
-    const FOREGROUND_COLOR: Color = Color::WHITE;
-    const BACKGROUND_COLOR: Color = Color::BLACK;
+    const PALETTE: &'static str = "classic";
+    const LETTERBOX_COLOR: Color = Color::BLACK;
+    const SCALING_MODE: ScalingMode = ScalingMode::Integer;
+    const DISPLAY_FILTER: DisplayFilter = DisplayFilter::None;
+    const FADE_STRENGTH: f32 = 0.6;
+    const FRAMES_PER_SECOND: u128 = 60;
     const INSTRUCTIONS_PER_SECOND: u128 = 600;
+    const ROM_DIRECTORY: &'static str = "roms";
+    const ROM_DATABASE_DIRECTORY: &'static str = "database";
+    const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+    const WINDOW_TITLE: &'static str = "chip chip chapa chapa 8";
+    const MENU_KEY: Keycode = Keycode::F1;
+    const RESET_KEY: Keycode = Keycode::F2;
+    const PAUSE_KEY: Keycode = Keycode::P;
+    const FRAME_ADVANCE_KEY: Keycode = Keycode::N;
+    const STATS_KEY: Keycode = Keycode::F3;
+    const MEMORY_VIEWER_KEY: Keycode = Keycode::F4;
+    const FAST_FORWARD_KEY: Keycode = Keycode::Tab;
+    const SPEED_DOWN_KEY: Keycode = Keycode::Minus;
+    const SPEED_UP_KEY: Keycode = Keycode::Equals;
+    const FAST_FORWARD_FACTOR: u32 = 4;
+    const INSTRUCTIONS_PER_SECOND_STEP: u128 = 60;
+    const MAX_INSTRUCTIONS_PER_SECOND: u128 = 60_000;
+    const CYCLE_PALETTE_KEY: Keycode = Keycode::F5;
+    const RECORD_KEY: Keycode = Keycode::F10;
+    const SCREENSHOT_KEY: Keycode = Keycode::F12;
+    const CAPTURE_DIRECTORY: &'static str = "captures";
+    const CHEAT_DIRECTORY: &'static str = "cheats";
+    const CAPTURE_SCALE: u32 = 10;
+    // A 4096x2048 frame, larger captures take gigabytes per frame
+    const MAX_CAPTURE_SCALE: u32 = 64;
 }
 
 struct ChipKeyboard;
 impl ChipKeyboard{
+    // Host key for every CHIP-8 key, 0x0 to 0xF
+    const HOST_KEY_NAMES: [&'static str; 16] = [
+        "X", "1", "2", "3", "Q", "W", "E", "A", "S", "D", "Z", "C", "4", "R", "F", "V",
+    ];
+
     const CHIP_KEY_0: usize = 0x0;
     const CHIP_KEY_1: usize = 0x1;
     const CHIP_KEY_2: usize = 0x2;
@@ -38,6 +120,56 @@ impl ChipKeyboard{
     const CHIP_KEY_F: usize = 0xF;
 }
 
+/* Pause and speed state changed at runtime by the hotkeys */
+struct RunControl {
+    paused: bool,
+    fast_forward: bool,
+    instructions_per_second: u128,
+    timing: TimingModel,
+    // VIP time the last frame ran over its budget, taken from the next one
+    vip_time_carry: Duration,
+}
+
+impl RunControl {
+    fn frames_per_tick(&self) -> u32 {
+        if self.fast_forward {
+            EmulatorConfig::FAST_FORWARD_FACTOR
+        }
+        else {
+            1
+        }
+    }
+
+    fn speed_up(&mut self) {
+        self.instructions_per_second = (self.instructions_per_second + EmulatorConfig::INSTRUCTIONS_PER_SECOND_STEP)
+            .min(EmulatorConfig::MAX_INSTRUCTIONS_PER_SECOND);
+    }
+
+    fn speed_down(&mut self) {
+        self.instructions_per_second = self.instructions_per_second
+            .saturating_sub(EmulatorConfig::INSTRUCTIONS_PER_SECOND_STEP)
+            .max(EmulatorConfig::INSTRUCTIONS_PER_SECOND_STEP);
+    }
+
+    fn instructions_per_frame(&self) -> u128 {
+        (self.instructions_per_second / EmulatorConfig::FRAMES_PER_SECOND).max(1)
+    }
+
+    fn status(&self) -> String {
+        let mut status = match self.timing {
+            TimingModel::Fixed => format!("{} IPS", self.instructions_per_second),
+            TimingModel::CosmacVip => String::from("VIP timing"),
+        };
+        if self.fast_forward {
+            status += &format!(" x{}", EmulatorConfig::FAST_FORWARD_FACTOR);
+        }
+        if self.paused {
+            status += ", PAUSED";
+        }
+        status
+    }
+}
+
 fn get_byte_0xF000(opcode: u16) -> u16{
     (opcode & 0xF000) >> 12
 }
@@ -62,6 +194,79 @@ fn get_bytes_0x00FF(opcode: u16) -> u16{
     opcode & 0x00FF
 }
 
+/* Behaviour differences between CHIP-8 interpreters, named after the
+ * quirks in the chip-8-database platforms.json
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Quirks {
+    // 8xy6/8xyE shift Vx in place instead of Vy into Vx
+    shift: bool,
+    // Fx55/Fx65 advance I by x instead of x + 1
+    memory_increment_by_x: bool,
+    // Fx55/Fx65 leave I unchanged
+    memory_leave_i_unchanged: bool,
+    // Sprites wrap around the screen edges instead of being clipped
+    wrap: bool,
+    // Bnnn jumps to xnn + Vx instead of nnn + V0
+    jump: bool,
+    // Dxyn waits for the start of the next frame
+    vblank: bool,
+    // 8xy1/8xy2/8xy3 reset VF to 0
+    logic: bool,
+}
+
+impl Quirks {
+    // What this emulator has always done, used for ROMs nothing is known about
+    const DEFAULT: Quirks = Quirks {
+        shift: true,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: true,
+        wrap: true,
+        jump: false,
+        vblank: false,
+        logic: false,
+    };
+
+    const COSMAC_VIP: Quirks = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: false,
+        jump: false,
+        vblank: true,
+        logic: true,
+    };
+
+    const SUPER_CHIP: Quirks = Quirks {
+        shift: true,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: true,
+        wrap: false,
+        jump: true,
+        vblank: false,
+        logic: false,
+    };
+
+    const XO_CHIP: Quirks = Quirks {
+        shift: false,
+        memory_increment_by_x: false,
+        memory_leave_i_unchanged: false,
+        wrap: true,
+        jump: false,
+        vblank: false,
+        logic: false,
+    };
+
+    fn for_platform(platform: Platform) -> Quirks {
+        match platform {
+            Platform::Chip8 => Quirks::COSMAC_VIP,
+            Platform::SuperChip => Quirks::SUPER_CHIP,
+            Platform::XoChip => Quirks::XO_CHIP,
+        }
+    }
+}
+
+#[derive(Clone)]
 struct ChipContext {
     memory: [u8; 4096],
     registers: [u8; 16],
@@ -74,12 +279,27 @@ struct ChipContext {
     sound_reg: u8,
 
     draw_flag: bool,
+    // Set at the start of every frame, cleared by Dxyn with the vblank quirk
+    vblank_ready: bool,
+    quirks: Quirks,
 
     frame_buffer: [[u8; 32]; 64],
     keyboard_keys: [bool; 16],
+
+    // Bytes loaded at PROGRAM_START by load_program
+    program_size: usize,
+    // Set instead of running an instruction that would break the machine, PC is left on it
+    fault: Option<String>,
 }
 
 impl ChipContext{
+    // Memory layout, the font sits in the interpreter area below the program
+    const FONT_START: u16 = 0x050;
+    const FONT_END: u16 = 0x0A0;
+    const PROGRAM_START: u16 = 0x200;
+    // The last address a whole instruction fits at
+    const MAX_PC: u16 = 0xFFE;
+
     const SPRITES: [[u8; 5]; 16] = [
         [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
         [0x20, 0x60, 0x20, 0x20, 0x70], // 1
@@ -102,7 +322,7 @@ impl ChipContext{
 
     fn reset() -> ChipContext{
         let mut memory: [u8; 4096] = [0; 4096];
-        let mut index: usize = 0x050;
+        let mut index = Self::FONT_START as usize;
 
         for sprite in Self::SPRITES {
             for byte in sprite {
@@ -116,31 +336,71 @@ impl ChipContext{
             registers: [0; 16],
             stack: [0; 16],
 
-            I: 0x050,
-            PC: 0x200,
+            I: Self::FONT_START,
+            PC: Self::PROGRAM_START,
             SP: 0,
             delay_reg: 0,
             sound_reg: 0,
 
             draw_flag: false,
+            vblank_ready: false,
+            quirks: Quirks::DEFAULT,
 
             frame_buffer: [[0; 32]; 64],
             keyboard_keys: [false; 16],
+
+            program_size: 0,
+            fault: None,
         }
     }
 
-    fn load_program(&mut self, program: &str){
-        let file = std::fs::read(program).unwrap();
-        for (index, byte) in file.iter().enumerate() {
+    /* A ROM file, "-" for stdin, a gzip file or a zip archive */
+    fn load_program(&mut self, program: &Path) -> Result<(), String>{
+        let file = source::read(program)?;
+        self.load_program_bytes(&file).map_err(|error| format!("{}: {}", program.display(), error))
+    }
+
+    /* ROM bytes already unpacked by source::read */
+    fn load_program_bytes(&mut self, program: &[u8]) -> Result<(), String>{
+        if program.len() > rom::MAX_ROM_SIZE {
+            return Err(format!("the ROM is {} bytes, only {} fit in memory", program.len(), rom::MAX_ROM_SIZE));
+        }
+        for (index, byte) in program.iter().enumerate() {
             self.memory[self.PC as usize + index] = *byte;
         }
+        self.program_size = program.len();
+        Ok(())
     }
 
-    fn fetch_opcode(&mut self) -> u16{
-        let operation1 = self.memory[self.PC as usize] as u16;
-        let operation2 = self.memory[(self.PC + 1) as usize] as u16;
-        let opcode: u16 = operation1 << 8 | operation2;
-        opcode
+    /* The instruction at address, wrapping around the end of memory */
+    fn opcode_at(&self, address: u16) -> u16 {
+        let address = address as usize & 0xFFF;
+        (self.memory[address] as u16) << 8 | self.memory[(address + 1) & 0xFFF] as u16
+    }
+
+    fn fetch_opcode(&self) -> u16{
+        self.opcode_at(self.PC)
+    }
+
+    /* The bytes loaded by load_program */
+    fn program(&self) -> &[u8] {
+        let start = Self::PROGRAM_START as usize;
+        &self.memory[start..start + self.program_size]
+    }
+
+    /* Bytes Fx33/Fx55 stored when opcode ran with I = i, call after it ran */
+    fn memory_writes(&self, opcode: u16, i: u16) -> Vec<(u16, u8)> {
+        let length = match (get_byte_0xF000(opcode), get_bytes_0x00FF(opcode)) {
+            (0xF, 0x33) => 3,
+            (0xF, 0x55) => get_byte_0x0F00(opcode) + 1,
+            _ => 0,
+        };
+        (0..length)
+            .map(|offset| {
+                let address = i.wrapping_add(offset) & 0xFFF;
+                (address, self.memory[address as usize])
+            })
+            .collect()
     }
 
     fn exec_opcode(&mut self) {
@@ -167,6 +427,10 @@ impl ChipContext{
                      * Set PC = stack[SP--]
                      */
                     0xEE => {
+                        if self.SP == 0 {
+                            self.fault = Some(format!("stack underflow at {:03X}: ret with an empty stack", self.PC));
+                            return;
+                        }
                         self.SP -= 1;
                         self.PC = self.stack[self.SP as usize] + 2;
                     }
@@ -191,11 +455,13 @@ impl ChipContext{
              * store stack[++SP] = PC, then PC=nnn
              */
             2 => {
+                if self.SP as usize >= self.stack.len() {
+                    self.fault = Some(format!("stack overflow at {:03X}: call 0x{:03X} with all {} stack entries in use",
+                        self.PC, get_bytes_0x0FFF(opcode), self.stack.len()));
+                    return;
+                }
                 self.stack[self.SP as usize] = self.PC;
                 self.SP += 1;
-                if self.SP > 0xF {
-                    eprintln!("stack overflow");
-                }
                 self.PC = get_bytes_0x0FFF(opcode);
             }
 
@@ -281,6 +547,9 @@ impl ChipContext{
                      */
                     0x1 => {
                         self.registers[x_register_index] |= self.registers[y_register_index];
+                        if self.quirks.logic {
+                            self.registers[0xF] = 0;
+                        }
                     }
 
                     /* "and Vx, Vy", "8xy2"
@@ -288,6 +557,9 @@ impl ChipContext{
                      */
                     0x2 => {
                         self.registers[x_register_index] &= self.registers[y_register_index];
+                        if self.quirks.logic {
+                            self.registers[0xF] = 0;
+                        }
                     }
 
                     /* "xor Vx, Vy", "8xy3"
@@ -295,6 +567,9 @@ impl ChipContext{
                      */
                     0x3 => {
                         self.registers[x_register_index] ^= self.registers[y_register_index];
+                        if self.quirks.logic {
+                            self.registers[0xF] = 0;
+                        }
                     }
 
                     /* "add Vx, Vy", "8xy4"
@@ -332,6 +607,9 @@ impl ChipContext{
                      * Set Vx = Vy >> 1, update VF = carry
                      */
                     0x6 => {
+                        if !self.quirks.shift {
+                            self.registers[x_register_index] = self.registers[y_register_index];
+                        }
                         let old_value = self.registers[x_register_index];
                         self.registers[x_register_index] >>= 1;
                         self.registers[0xF] = old_value & 0x1;
@@ -354,6 +632,9 @@ impl ChipContext{
                      * set Vx = Vy << 1, update VF = carry
                      */
                     0xE => {
+                        if !self.quirks.shift {
+                            self.registers[x_register_index] = self.registers[y_register_index];
+                        }
                         let old_value = self.registers[x_register_index];
                         self.registers[x_register_index] <<= 1;
                         self.registers[0xF] = (old_value & 0x80) >> 7;
@@ -392,11 +673,12 @@ impl ChipContext{
 
             /* "jp V0, N", "Bnnn"
              * Jump to location nnn + V0
-             * Set PC = nnn + V0
+             * Set PC = nnn + V0, or xnn + Vx with the jump quirk
              */
             0xB => {
                 let opcode_param = get_bytes_0x0FFF(opcode);
-                self.PC = opcode_param.wrapping_add(self.registers[0] as u16);
+                let register_index = if self.quirks.jump { get_byte_0x0F00(opcode) as usize } else { 0 };
+                self.PC = opcode_param.wrapping_add(self.registers[register_index] as u16);
             }
 
             /* "rnd Vx, K", "Cxkk"
@@ -414,16 +696,27 @@ impl ChipContext{
              * Display n-byte starting at memory location I at (Vx, Vy), set VF = collision
              */
             0xD => {
-                let x = self.registers[get_byte_0x0F00(opcode) as usize] as u16;
-                let y = self.registers[get_byte_0x00F0(opcode) as usize] as u16;
+                if self.quirks.vblank {
+                    if !self.vblank_ready {
+                        // Try again once the next frame starts
+                        return;
+                    }
+                    self.vblank_ready = false;
+                }
+
+                let x = self.registers[get_byte_0x0F00(opcode) as usize] as u16 % 64;
+                let y = self.registers[get_byte_0x00F0(opcode) as usize] as u16 % 32;
                 let bytes_amount = get_byte_0x000F(opcode);
                 let mut pixel: u8;
 
                 self.registers[0xF] = 0;
 
                 for yline in 0..bytes_amount {
-                    pixel = self.memory[(self.I + yline) as usize];
+                    pixel = self.memory[self.i_address(yline)];
                     for xline in 0..8 {
+                        if !self.quirks.wrap && (x + xline >= 64 || y + yline >= 32) {
+                            continue;
+                        }
                         if pixel & (0x80 >> xline) != 0{
                             if(self.frame_buffer[((x + xline) % 64) as usize][((y + yline) % 32) as usize]) == 1{
                                 self.registers[0xF] = 1;
@@ -483,13 +776,13 @@ impl ChipContext{
                      * Wait for a key press, store the value of the key in Vx
                      */
                     0x0A => {
-                        let mut is_key_pressed: bool = false;
-                        while !is_key_pressed {
-                            for i in 0..16 {
-                                if self.keyboard_keys[i]{
-                                    self.registers[x_register_index] = i as u8;
-                                    is_key_pressed = true;
-                                }
+                        match self.keyboard_keys.iter().position(|pressed| *pressed) {
+                            Some(key) => {
+                                self.registers[x_register_index] = key as u8;
+                            }
+                            None => {
+                                // Run this instruction again until input between frames presses a key
+                                return;
                             }
                         }
                     }
@@ -512,23 +805,23 @@ impl ChipContext{
                      * Set I = I + Vx
                      */
                     0x1E => {
-                        self.I += self.registers[x_register_index] as u16;
+                        self.I = self.I.wrapping_add(self.registers[x_register_index] as u16);
                     }
 
                     /* "ld f, Vx", "Fx29"
                      * Set I = location of sprite for digit Vx
                      */
                     0x29 => {
-                        self.I = 0x050 + (5 * self.registers[x_register_index]) as u16;
+                        self.I = Self::FONT_START + (5 * self.registers[x_register_index]) as u16;
                     }
 
                     /* "ld b, Vx", "Fx33"
                      * Store BCD representation of Vx in memory location I, I+1, I+2
                      */
                     0x33 => {
-                        self.memory[self.I as usize] = self.registers[x_register_index] / 100;
-                        self.memory[(self.I + 1) as usize] = (self.registers[x_register_index] / 10) % 10;
-                        self.memory[(self.I + 2) as usize] = self.registers[x_register_index] % 10;
+                        self.memory[self.i_address(0)] = self.registers[x_register_index] / 100;
+                        self.memory[self.i_address(1)] = (self.registers[x_register_index] / 10) % 10;
+                        self.memory[self.i_address(2)] = self.registers[x_register_index] % 10;
                     }
 
                     /* "ld [i], Vx", "Fx55"
@@ -536,8 +829,9 @@ impl ChipContext{
                      */
                     0x55 => {
                         for i in 0..x_register_index+1 {
-                            self.memory[self.I as usize + i] = self.registers[i];
+                            self.memory[self.i_address(i as u16)] = self.registers[i];
                         }
+                        self.increment_i_after_load_store(x_register_index);
                     }
 
                     /* "ld Vx, [i]", "Fx65"
@@ -545,8 +839,9 @@ impl ChipContext{
                      */
                     0x65 => {
                         for i in 0..x_register_index+1 {
-                            self.registers[i] = self.memory[self.I as usize + i];
+                            self.registers[i] = self.memory[self.i_address(i as u16)];
                         }
+                        self.increment_i_after_load_store(x_register_index);
                     }
 
                     _ => {
@@ -564,6 +859,23 @@ impl ChipContext{
         }
     }
 
+    /* I + offset, wrapping around the end of memory like the address of an opcode */
+    fn i_address(&self, offset: u16) -> usize {
+        (self.I as usize + offset as usize) & 0xFFF
+    }
+
+    fn increment_i_after_load_store(&mut self, x_register_index: usize) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        if self.quirks.memory_increment_by_x {
+            self.I = self.I.wrapping_add(x_register_index as u16);
+        }
+        else {
+            self.I = self.I.wrapping_add(x_register_index as u16 + 1);
+        }
+    }
+
     fn update_timers(&mut self) {
         if self.delay_reg > 0 {
             self.delay_reg -= 1;
@@ -573,174 +885,1033 @@ impl ChipContext{
         }
     }
 
-    fn draw_graphics(&mut self, canvas: &mut Canvas<sdl2::video::Window>){
-        for i in 0..64{
-            for j in 0..32{
-                if self.frame_buffer[i][j] == 1 {
-                    canvas.set_draw_color(EmulatorConfig::FOREGROUND_COLOR);
-                }
-                else {
-                    canvas.set_draw_color(EmulatorConfig::BACKGROUND_COLOR);
-                }
+    fn draw_graphics(&mut self, canvas: &mut Canvas<sdl2::video::Window>, renderer: &mut Renderer){
+        renderer.draw(canvas, &self.frame_buffer);
+        self.draw_flag = false;
+    }
+
+    fn read_input(&mut self, event: &Event){
+        match event {
+
+            Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+            Event::Window { win_event: WindowEvent::Exposed, .. } => {
+                self.draw_flag = true;
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_4] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::W), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_5] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::E), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_6] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::R), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_D] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::A), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_7] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::S), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_8] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::D), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_9] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::F), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_E] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Z), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_A] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::X), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_0] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::C), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_B] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::V), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_F] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Num1), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_1] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Num2), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_2] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Num3), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_3] = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Num4), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_C] = true;
+            }
+
+            ////
+            
+            Event::KeyUp { keycode: Some(Keycode::Q), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_4] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::W), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_5] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::E), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_6] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::R), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_D] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::A), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_7] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::S), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_8] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::D), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_9] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::F), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_E] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::Z), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_A] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::X), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_0] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::C), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_B] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::V), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_F] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::Num1), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_1] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::Num2), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_2] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::Num3), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_3] = false;
+            }
+            Event::KeyUp { keycode: Some(Keycode::Num4), .. } => {
+                self.keyboard_keys[ChipKeyboard::CHIP_KEY_C] = false;
+            }
 
-                let rect = Rect::new(
-                    i as i32 * EmulatorConfig::SCREEN_SCALE as i32,
-                    j as i32 * EmulatorConfig::SCREEN_SCALE as i32,
-                    EmulatorConfig::SCREEN_SCALE,
-                    EmulatorConfig::SCREEN_SCALE
-                    );
-                let _ = canvas.fill_rect(rect);
+            _ => {
             }
         }
-        canvas.present();
-        self.draw_flag = false;
     }
+}
 
-    fn read_input(&mut self, event_pump: EventPollIterator, loop_condition: &mut bool){
-        for event in event_pump {
-            match event {
+/* Optional tools that look at every executed instruction */
+struct Instrumentation {
+    profiler: Option<Profiler>,
+    tracer: Option<Tracer>,
+    gdb: Option<GdbServer>,
+    script: Option<Script>,
+    symbols: SymbolMap,
+    breakpoints: HashSet<u16>,
+    // Set when a breakpoint stopped execution, until the main loop picked it up
+    breakpoint_hit: bool,
+    // The breakpoint execution stopped at, it doesn't stop there again when resumed
+    resume_from: Option<u16>,
+    // Stops on calls and rets that unbalance the stack
+    stack_check: Option<StackChecker>,
+    // Set when the stack check stopped execution, until the main loop picked it up
+    stack_problem: Option<String>,
+    origins: Option<MemoryOrigins>,
+}
 
-                Event::Quit { .. } |
-                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    *loop_condition = false;
-                }
+impl Instrumentation {
+    fn rom_started(&mut self, chip8: &mut ChipContext, name: &str) {
+        if self.profiler.is_some() {
+            self.profiler = Some(Profiler::new());
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.rom_started(name);
+        }
+        if let Some(origins) = self.origins.as_mut() {
+            origins.rom_started(chip8);
+        }
+        if let Some(script) = self.script.as_mut() {
+            script.rom_started(chip8);
+        }
+        if let Some(stack_check) = self.stack_check.as_mut() {
+            stack_check.rom_started();
+        }
+    }
 
-                Event::KeyDown { keycode: Some(Keycode::Q), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_4] = true;
-                }
-                Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_5] = true;
-                }
-                Event::KeyDown { keycode: Some(Keycode::E), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_6] = true;
-                }
-                Event::KeyDown { keycode: Some(Keycode::R), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_D] = true;
+    fn breakpoint_hit(&self) -> bool {
+        self.breakpoint_hit || self.stack_problem.is_some() || self.gdb.as_ref().is_some_and(GdbServer::breakpoint_hit)
+    }
+
+    fn take_breakpoint_hit(&mut self) -> bool {
+        let gdb_hit = self.gdb.as_mut().is_some_and(GdbServer::take_breakpoint_hit);
+        std::mem::take(&mut self.breakpoint_hit) || gdb_hit
+    }
+
+    fn frame_done(&mut self, chip8: &mut ChipContext) {
+        if let Some(script) = self.script.as_mut() {
+            script.frame_done(chip8);
+        }
+    }
+
+    /* Call after an input event, with the keys from before it */
+    fn keys_changed(&mut self, chip8: &mut ChipContext, previous_keys: [bool; 16]) {
+        if let Some(script) = self.script.as_mut() {
+            for (key, was_pressed) in previous_keys.into_iter().enumerate() {
+                if chip8.keyboard_keys[key] != was_pressed {
+                    script.key_changed(chip8, key, !was_pressed);
                 }
-                Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_7] = true;
+            }
+        }
+    }
+
+    /* Runs the instruction at PC, false if a breakpoint or a fault stopped it */
+    fn step(&mut self, chip8: &mut ChipContext) -> bool {
+        if let Some(gdb) = self.gdb.as_mut() {
+            if gdb.should_stop(chip8) {
+                return false;
+            }
+        }
+        let resuming = self.resume_from.take() == Some(chip8.PC);
+        if !resuming && self.breakpoints.contains(&chip8.PC) {
+            self.resume_from = Some(chip8.PC);
+            self.breakpoint_hit = true;
+            return false;
+        }
+        if let Some(stack_check) = self.stack_check.as_mut().filter(|_| !resuming) {
+            if let Some(problem) = stack_check.before_instruction(chip8, &self.symbols) {
+                self.resume_from = Some(chip8.PC);
+                self.stack_problem = Some(problem);
+                return false;
+            }
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(chip8);
+        }
+        if let Some(script) = self.script.as_mut() {
+            script.before_instruction(chip8);
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.before_instruction(chip8);
+        }
+        if let Some(origins) = self.origins.as_mut() {
+            origins.before_instruction(chip8, &self.symbols);
+        }
+        let (pc, opcode, i, sp) = (chip8.PC, chip8.fetch_opcode(), chip8.I, chip8.SP);
+        chip8.exec_opcode();
+        if chip8.fault.is_some() {
+            return false;
+        }
+        if let Some(stack_check) = self.stack_check.as_mut() {
+            stack_check.after_instruction(chip8, pc, sp);
+        }
+        if let Some(origins) = self.origins.as_mut() {
+            origins.after_instruction(chip8, pc, opcode, i);
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.after_instruction(chip8);
+        }
+        if let Some(script) = self.script.as_mut().filter(|script| script.has_hook("on_memory_write")) {
+            for (address, value) in chip8.memory_writes(opcode, i) {
+                script.memory_written(chip8, address, value);
+            }
+        }
+        true
+    }
+}
+
+/* Runs the instructions of one 60Hz frame and ticks the timers */
+fn run_frame(chip8: &mut ChipContext, run_control: &mut RunControl, osd: &mut Osd, instrumentation: &mut Instrumentation) {
+    chip8.vblank_ready = true;
+    match run_control.timing {
+        TimingModel::Fixed => {
+            for _ in 0..run_control.instructions_per_frame() {
+                if !instrumentation.step(chip8) {
+                    break;
                 }
-                Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_8] = true;
+                osd.count_instruction();
+            }
+        }
+        TimingModel::CosmacVip => {
+            let mut elapsed = run_control.vip_time_carry;
+            while elapsed < timing::FRAME_TIME {
+                let opcode = chip8.fetch_opcode();
+                let instruction_time = timing::vip_instruction_time(opcode, &chip8.registers);
+                if !instrumentation.step(chip8) {
+                    elapsed = elapsed.max(timing::FRAME_TIME);
+                    break;
                 }
-                Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_9] = true;
+                elapsed += instruction_time;
+                osd.count_instruction();
+                // The VIP spends the rest of the frame waiting for the vertical blank after drawing
+                if timing::is_draw(opcode) {
+                    elapsed = elapsed.max(timing::FRAME_TIME);
+                    break;
                 }
-                Event::KeyDown { keycode: Some(Keycode::F), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_E] = true;
+            }
+            run_control.vip_time_carry = elapsed - timing::FRAME_TIME;
+        }
+    }
+    chip8.update_timers();
+    osd.count_frame();
+    instrumentation.frame_done(chip8);
+}
+
+const DEBUG_HELP: &str = "\
+break LOCATION               stop before the instruction at a label or hex address
+delete LOCATION              remove a breakpoint
+breakpoints                  list the breakpoints
+bt                           show the call stack
+disasm [LOCATION] [COUNT]    disassemble COUNT instructions from LOCATION (default PC)";
+
+fn run_debug_command(words: &[&str], chip8: &ChipContext, instrumentation: &mut Instrumentation) -> Option<Result<String, String>> {
+    let symbols = &instrumentation.symbols;
+    let resolve = |location: &str| symbols.resolve(location).ok_or(format!("unknown label or address \"{}\"", location));
+    let result = match words {
+        ["break", location] => resolve(location).map(|address| {
+            instrumentation.breakpoints.insert(address);
+            format!("breakpoint at {:03X} {}", address, instrumentation.symbols.describe(address))
+        }),
+        ["delete", location] => resolve(location).and_then(|address| {
+            if instrumentation.breakpoints.remove(&address) {
+                Ok(format!("deleted breakpoint at {:03X}", address))
+            }
+            else {
+                Err(format!("no breakpoint at {:03X}", address))
+            }
+        }),
+        ["breakpoints"] => {
+            let mut addresses: Vec<u16> = instrumentation.breakpoints.iter().copied().collect();
+            addresses.sort();
+            let lines: Vec<String> = addresses
+                .iter()
+                .map(|address| format!("{:03X}  {}", address, symbols.describe(*address)))
+                .collect();
+            Ok(if lines.is_empty() { String::from("no breakpoints") } else { lines.join("\n") })
+        }
+        ["bt"] => Ok(callstack::backtrace(chip8, symbols)),
+        ["disasm", arguments @ ..] if arguments.len() <= 2 => {
+            let start = arguments.first().map_or(Ok(chip8.PC), |location| resolve(location));
+            let count = arguments.get(1).map_or(Ok(10), |count| count.parse::<u16>().map_err(|_| format!("invalid count \"{}\"", count)));
+            start.and_then(|start| {
+                let mut lines = Vec::new();
+                for index in 0..count? {
+                    let address = start.wrapping_add(index.wrapping_mul(2)) & 0xFFF;
+                    let opcode = chip8.opcode_at(address);
+                    if let Some(label) = symbols.label(address) {
+                        lines.push(format!("{}:", label));
+                    }
+                    let marker = if address == chip8.PC { ">" } else { " " };
+                    lines.push(format!("{} {:03X}  {:04X}  {}", marker, address, opcode, disasm::disassemble_labeled(opcode, symbols)));
                 }
-                Event::KeyDown { keycode: Some(Keycode::Z), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_A] = true;
+                Ok(lines.join("\n"))
+            })
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+fn run_console_command(line: &str, chip8: &mut ChipContext, cheats: &mut CheatEngine, instrumentation: &mut Instrumentation) -> Result<String, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        [] => Ok(String::new()),
+        ["help"] => Ok(format!("{}\n{}", DEBUG_HELP, CheatEngine::HELP)),
+        _ => run_debug_command(&words, chip8, instrumentation)
+            .or_else(|| cheats.command(&words, chip8))
+            .unwrap_or_else(|| Err(format!("unknown command \"{}\", type help for a list", line.trim()))),
+    }
+}
+
+fn finish_recording(recorder: Recorder) {
+    match recorder.finish() {
+        Ok(path) => println!("saved recording {}", path.display()),
+        Err(error) => eprintln!("{}", error),
+    }
+}
+
+fn start_rom(chip8: &mut ChipContext, path: &Path, database: &RomDatabase) -> Result<Option<RomSettings>, String> {
+    let rom = source::read(path)?;
+    let mut loaded = ChipContext::reset();
+    loaded.load_program_bytes(&rom).map_err(|error| format!("{}: {}", path.display(), error))?;
+    loaded.draw_flag = true;
+
+    let settings = database.lookup(&rom);
+    if let Some(settings) = &settings {
+        loaded.quirks = settings.quirks;
+        println!("{}: {} on {} ({}), {:?}",
+            path.display(), settings.title, settings.platform.name(), settings.platform_id, settings.quirks);
+        if !settings.key_hints.is_empty() {
+            let hints: Vec<String> = settings.key_hints
+                .iter()
+                .map(|(action, key)| format!("{}={}", action, ChipKeyboard::HOST_KEY_NAMES[*key as usize]))
+                .collect();
+            println!("keys: {}", hints.join(" "));
+        }
+    }
+    else {
+        let guess = rom::guess_quirks(&rom);
+        loaded.quirks = guess.quirks;
+        println!("{}: not in the database, guessed {} quirks ({}), {:?}",
+            path.display(), guess.platform.name(), guess.reasons.join("; "), guess.quirks);
+    }
+    *chip8 = loaded;
+    Ok(settings)
+}
+
+fn quirk_profile_name(settings: &Option<RomSettings>, chip8: &ChipContext) -> String {
+    match settings {
+        Some(settings) => settings.platform_id.clone(),
+        None => format!("guessed {}", rom::guess_quirks(chip8.program()).platform.name()),
+    }
+}
+
+fn rom_name(path: &Path, settings: &Option<RomSettings>) -> String {
+    match settings {
+        Some(settings) => settings.title.clone(),
+        None => path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+    }
+}
+
+fn update_title(canvas: &mut Canvas<sdl2::video::Window>, rom_name: &str, run_control: &RunControl, reload_count: u32) {
+    let mut title = format!("{} - {} [{}]", EmulatorConfig::WINDOW_TITLE, rom_name, run_control.status());
+    if reload_count > 0 {
+        title += &format!(" (reloaded {}x)", reload_count);
+    }
+    let _ = canvas.window_mut().set_title(&title);
+}
+
+/* Tick rate and colors from the database, or the defaults for unknown ROMs */
+fn apply_rom_settings(settings: Option<RomSettings>, run_control: &mut RunControl, renderer: &mut Renderer, palette: &Palette) {
+    run_control.instructions_per_second = match settings.as_ref().and_then(|settings| settings.tick_rate) {
+        Some(tick_rate) => tick_rate as u128 * EmulatorConfig::FRAMES_PER_SECOND,
+        None => EmulatorConfig::INSTRUCTIONS_PER_SECOND,
+    };
+    match settings.and_then(|settings| settings.palette(palette)) {
+        Some(rom_palette) => renderer.set_palette(rom_palette),
+        None => renderer.set_palette(palette.clone()),
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    let subcommand = args.peek().cloned().unwrap_or_default();
+    let subcommand_result = match subcommand.as_str() {
+        "bench" => Some(bench::run(args.by_ref().skip(1))),
+        "difftrace" => Some(difftrace::run(args.by_ref().skip(1))),
+        "lint" => Some(lint::run(args.by_ref().skip(1))),
+        _ => None,
+    };
+    if let Some(result) = subcommand_result {
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut scaling_mode = EmulatorConfig::SCALING_MODE;
+    let mut filter_name: Option<String> = None;
+    let mut fade_strength = EmulatorConfig::FADE_STRENGTH;
+    let mut palette_name = String::from(EmulatorConfig::PALETTE);
+    let mut custom_colors: Option<String> = None;
+    let mut capture_scale = EmulatorConfig::CAPTURE_SCALE;
+    let mut rom_directory = PathBuf::from(EmulatorConfig::ROM_DIRECTORY);
+    let mut rom_path: Option<PathBuf> = None;
+    let mut database_directory = PathBuf::from(EmulatorConfig::ROM_DATABASE_DIRECTORY);
+    let mut watch_rom = false;
+    let mut keep_on_reload = false;
+    let mut timing_model = TimingModel::Fixed;
+    let mut pacing_mode = PacingMode::Sleep;
+    let mut profile_path: Option<PathBuf> = None;
+    let mut trace_path: Option<PathBuf> = None;
+    let mut gdb_port: Option<u16> = None;
+    let mut script_path: Option<PathBuf> = None;
+    let mut use_console = false;
+    let mut symbols_path: Option<PathBuf> = None;
+    let mut stack_check = false;
+    let mut check_memory = false;
+    let mut trace_range: (u16, u16) = (0x000, 0xFFF);
+    let mut trace_classes: u16 = 0xFFFF;
+    let mut record_path: Option<PathBuf> = None;
+    let mut record_duration: Option<Duration> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scaling" => {
+                let name = args.next().unwrap_or_default();
+                scaling_mode = ScalingMode::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("unknown scaling mode \"{}\", expected integer, fit or stretch", name);
+                    std::process::exit(1);
+                });
+            }
+            "--filter" => {
+                filter_name = Some(args.next().unwrap_or_default());
+            }
+            "--filter-strength" => {
+                let value = args.next().unwrap_or_default();
+                fade_strength = value.parse().unwrap_or_else(|_| {
+                    eprintln!("invalid filter strength \"{}\", expected a number between 0 and 1", value);
+                    std::process::exit(1);
+                });
+            }
+            "--palette" => {
+                palette_name = args.next().unwrap_or_default();
+            }
+            "--colors" => {
+                custom_colors = Some(args.next().unwrap_or_default());
+            }
+            "--capture-scale" => {
+                let value = args.next().unwrap_or_default();
+                capture_scale = value.parse()
+                    .ok()
+                    .filter(|scale| (1..=EmulatorConfig::MAX_CAPTURE_SCALE).contains(scale))
+                    .unwrap_or_else(|| {
+                        eprintln!("invalid capture scale \"{}\", expected 1-{}", value, EmulatorConfig::MAX_CAPTURE_SCALE);
+                        std::process::exit(1);
+                    });
+            }
+            "--record" => {
+                record_path = Some(PathBuf::from(args.next().unwrap_or_default()));
+            }
+            "--record-seconds" => {
+                let value = args.next().unwrap_or_default();
+                let seconds: f32 = value.parse().unwrap_or_else(|_| {
+                    eprintln!("invalid recording length \"{}\", expected seconds", value);
+                    std::process::exit(1);
+                });
+                record_duration = Some(Duration::from_secs_f32(seconds.max(0.0)));
+            }
+            "--database" => {
+                database_directory = PathBuf::from(args.next().unwrap_or_default());
+            }
+            "--watch" => {
+                watch_rom = true;
+            }
+            "--keep-on-reload" => {
+                keep_on_reload = true;
+            }
+            "--timing" => {
+                let name = args.next().unwrap_or_default();
+                timing_model = TimingModel::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("unknown timing model \"{}\", expected fixed or vip", name);
+                    std::process::exit(1);
+                });
+            }
+            "--vsync" => {
+                pacing_mode = PacingMode::Vsync;
+            }
+            "--uncapped" => {
+                pacing_mode = PacingMode::Uncapped;
+            }
+            "--profile" => {
+                profile_path = Some(PathBuf::from(args.next().unwrap_or_default()));
+            }
+            "--trace" => {
+                trace_path = Some(PathBuf::from(args.next().unwrap_or_default()));
+            }
+            "--trace-range" => {
+                let value = args.next().unwrap_or_default();
+                trace_range = Tracer::parse_address_range(&value).unwrap_or_else(|| {
+                    eprintln!("invalid trace range \"{}\", expected hex addresses like 200-3FF", value);
+                    std::process::exit(1);
+                });
+            }
+            "--trace-ops" => {
+                let value = args.next().unwrap_or_default();
+                trace_classes = Tracer::parse_opcode_classes(&value).unwrap_or_else(|| {
+                    eprintln!("invalid opcode classes \"{}\", expected first opcode digits like 8,D,F", value);
+                    std::process::exit(1);
+                });
+            }
+            "--gdb" => {
+                let value = args.next().unwrap_or_default();
+                gdb_port = Some(value.parse().unwrap_or_else(|_| {
+                    eprintln!("invalid gdb port \"{}\"", value);
+                    std::process::exit(1);
+                }));
+            }
+            "--script" => {
+                script_path = Some(PathBuf::from(args.next().unwrap_or_default()));
+            }
+            "--console" => {
+                use_console = true;
+            }
+            "--symbols" => {
+                symbols_path = Some(PathBuf::from(args.next().unwrap_or_default()));
+            }
+            "--stack-check" => {
+                stack_check = true;
+            }
+            "--check-memory" => {
+                check_memory = true;
+            }
+            "--rom-dir" => {
+                rom_directory = PathBuf::from(args.next().unwrap_or_default());
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("unknown argument \"{}\"", arg);
+                std::process::exit(1);
+            }
+            _ => {
+                rom_path = Some(PathBuf::from(arg));
+            }
+        }
+    }
+
+    let display_filter = match filter_name {
+        Some(name) => DisplayFilter::from_name(&name, fade_strength).unwrap_or_else(|| {
+            eprintln!("unknown display filter \"{}\", expected none, fade or blend", name);
+            std::process::exit(1);
+        }),
+        None => EmulatorConfig::DISPLAY_FILTER,
+    };
+
+    let mut palette = Palette::by_name(&palette_name).unwrap_or_else(|| {
+        eprintln!("unknown palette \"{}\", expected one of {}", palette_name, Palette::names().join(", "));
+        std::process::exit(1);
+    });
+    if let Some(colors) = custom_colors {
+        palette = Palette::parse_custom(&colors, &palette).unwrap_or_else(|| {
+            eprintln!("invalid colors \"{}\", expected 2 to 4 comma separated RRGGBB values", colors);
+            std::process::exit(1);
+        });
+    }
+
+    let database = if database_directory.is_dir() {
+        RomDatabase::load(&database_directory).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            RomDatabase::empty()
+        })
+    }
+    else {
+        RomDatabase::empty()
+    };
+    if database.len() > 0 {
+        println!("loaded {} ROMs from {}", database.len(), database_directory.display());
+    }
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(EmulatorConfig::WINDOW_TITLE, 
+            EmulatorConfig::SCREEN_WIDTH * EmulatorConfig::SCREEN_SCALE, 
+            EmulatorConfig::SCREEN_HEIGHT * EmulatorConfig::SCREEN_SCALE) 
+        .position_centered()
+        .resizable()
+        .build()
+        .unwrap();
+    let mut canvas_builder = window.into_canvas();
+    if pacing_mode == PacingMode::Vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let mut canvas = canvas_builder.build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut renderer = Renderer::new(&texture_creator, scaling_mode, display_filter, palette.clone());
+
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut osd = Osd::new();
+
+    let symbols = match &symbols_path {
+        Some(path) => {
+            let symbols = SymbolMap::load(path).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            });
+            println!("loaded {} symbols from {}", symbols.len(), path.display());
+            symbols
+        }
+        None => SymbolMap::empty(),
+    };
+
+    let mut chip8: ChipContext = ChipContext::reset();
+    let mut instrumentation = Instrumentation {
+        profiler: profile_path.as_ref().map(|_| Profiler::new()),
+        tracer: trace_path.as_ref().map(|path| Tracer::create(path, trace_range, trace_classes, symbols.clone()).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })),
+        gdb: gdb_port.map(|port| GdbServer::listen(port).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })),
+        script: script_path.as_ref().map(|path| Script::load(path).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })),
+        symbols,
+        breakpoints: HashSet::new(),
+        breakpoint_hit: false,
+        resume_from: None,
+        stack_check: if stack_check { Some(StackChecker::new()) } else { None },
+        stack_problem: None,
+        origins: if check_memory { Some(MemoryOrigins::new()) } else { None },
+    };
+    let mut run_control = RunControl {
+        paused: false,
+        fast_forward: false,
+        instructions_per_second: EmulatorConfig::INSTRUCTIONS_PER_SECOND,
+        timing: timing_model,
+        vip_time_carry: Duration::ZERO,
+    };
+    let mut cheats = CheatEngine::new();
+    let mut menu: Option<RomMenu> = None;
+    let mut memory_viewer: Option<MemoryViewer> = None;
+    let mut watcher: Option<FileWatcher> = None;
+    let mut reload_count: u32 = 0;
+    let mut current_rom = PathBuf::new();
+    let mut current_rom_name = String::new();
+    match rom_path {
+        Some(path) => {
+            let settings = start_rom(&mut chip8, &path, &database).unwrap_or_else(|error| {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            });
+            current_rom_name = rom_name(&path, &settings);
+            instrumentation.rom_started(&mut chip8, &current_rom_name);
+            cheats.rom_started(&chip8);
+            osd.show_message(&format!("quirks: {}", quirk_profile_name(&settings, &chip8)));
+            apply_rom_settings(settings, &mut run_control, &mut renderer, &palette);
+            update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
+            if watch_rom {
+                watcher = Some(FileWatcher::new(&path, EmulatorConfig::WATCH_INTERVAL));
+            }
+            current_rom = path;
+        }
+        None => {
+            menu = Some(RomMenu::scan(&rom_directory));
+        }
+    }
+    // After the ROM is loaded, a ROM from "-" reads stdin too
+    let console = if use_console { Some(Console::start()) } else { None };
+
+    let mut recorder: Option<Recorder> = None;
+    if let Some(path) = record_path {
+        recorder = Some(Recorder::start(&path, renderer.palette(), capture_scale, record_duration).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }));
+    }
+
+    let mut running: bool = true;
+    let mut advance_frame: bool = false;
+    let mut pacer = FramePacer::new(pacing_mode, timing::FRAME_TIME);
+
+    while running{
+        let frames_due = pacer.wait_for_frames();
+
+        for event in event_pump.poll_iter() {
+            if let Some(viewer) = memory_viewer.as_mut() {
+                if event.get_window_id() == Some(viewer.window_id()) {
+                    if !viewer.handle_event(&event, &mut chip8, run_control.paused) {
+                        memory_viewer = None;
+                    }
+                    continue;
                 }
-                Event::KeyDown { keycode: Some(Keycode::X), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_0] = true;
+            }
+
+            if let Some(active_menu) = menu.as_mut() {
+                match event {
+                    Event::Quit { .. } |
+                    Event::Window { win_event: WindowEvent::Close, .. } |
+                    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                        running = false;
+                    }
+
+                    Event::KeyDown { keycode: Some(EmulatorConfig::CYCLE_PALETTE_KEY), repeat: false, .. } => {
+                        renderer.cycle_palette();
+                    }
+
+                    Event::KeyDown { keycode: Some(keycode), .. } => {
+                        if let MenuAction::Launch(path) = active_menu.handle_key(keycode) {
+                            match start_rom(&mut chip8, &path, &database) {
+                                Ok(settings) => {
+                                    current_rom_name = rom_name(&path, &settings);
+                                    instrumentation.rom_started(&mut chip8, &current_rom_name);
+                                    cheats.rom_started(&chip8);
+                                    osd.show_message(&format!("quirks: {}", quirk_profile_name(&settings, &chip8)));
+                                    apply_rom_settings(settings, &mut run_control, &mut renderer, &palette);
+                                    run_control.paused = false;
+                                    reload_count = 0;
+                                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
+                                    if watch_rom {
+                                        watcher = Some(FileWatcher::new(&path, EmulatorConfig::WATCH_INTERVAL));
+                                    }
+                                    current_rom = path;
+                                    menu = None;
+                                }
+                                Err(error) => active_menu.set_error(error),
+                            }
+                        }
+                    }
+
+                    _ => {}
                 }
-                Event::KeyDown { keycode: Some(Keycode::C), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_B] = true;
+                continue;
+            }
+
+            match event {
+                Event::Quit { .. } |
+                Event::Window { win_event: WindowEvent::Close, .. } |
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                    running = false;
                 }
-                Event::KeyDown { keycode: Some(Keycode::V), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_F] = true;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::MENU_KEY), repeat: false, .. } => {
+                    menu = Some(RomMenu::scan(&rom_directory));
+                    watcher = None;
+                    let _ = canvas.window_mut().set_title(EmulatorConfig::WINDOW_TITLE);
                 }
-                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_1] = true;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::RESET_KEY), repeat: false, .. } => {
+                    let quirks = chip8.quirks;
+                    chip8 = ChipContext::reset();
+                    chip8.quirks = quirks;
+                    chip8.draw_flag = true;
+                    match chip8.load_program(&current_rom) {
+                        Ok(()) => {
+                            instrumentation.rom_started(&mut chip8, &current_rom_name);
+                            cheats.rom_started(&chip8);
+                            osd.show_message("reset");
+                        }
+                        Err(error) => eprintln!("{}", error),
+                    }
                 }
-                Event::KeyDown { keycode: Some(Keycode::Num2), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_2] = true;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::PAUSE_KEY), repeat: false, .. } => {
+                    run_control.paused = !run_control.paused;
+                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
                 }
-                Event::KeyDown { keycode: Some(Keycode::Num3), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_3] = true;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::STATS_KEY), repeat: false, .. } => {
+                    osd.toggle_stats();
                 }
-                Event::KeyDown { keycode: Some(Keycode::Num4), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_C] = true;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::MEMORY_VIEWER_KEY), repeat: false, .. } => {
+                    if memory_viewer.is_some() {
+                        memory_viewer = None;
+                    }
+                    else {
+                        match MemoryViewer::open(&video_subsystem) {
+                            Ok(viewer) => memory_viewer = Some(viewer),
+                            Err(error) => eprintln!("cannot open memory viewer: {}", error),
+                        }
+                    }
                 }
 
-                ////
-                
-                Event::KeyUp { keycode: Some(Keycode::Q), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_4] = false;
+                Event::KeyDown { keycode: Some(EmulatorConfig::FRAME_ADVANCE_KEY), .. } => {
+                    if run_control.paused {
+                        advance_frame = true;
+                    }
                 }
-                Event::KeyUp { keycode: Some(Keycode::W), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_5] = false;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::FAST_FORWARD_KEY), repeat: false, .. } => {
+                    run_control.fast_forward = !run_control.fast_forward;
+                    if run_control.fast_forward {
+                        osd.show_message(&format!("fast-forward x{}", EmulatorConfig::FAST_FORWARD_FACTOR));
+                    }
+                    else {
+                        osd.show_message("normal speed");
+                    }
+                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
                 }
-                Event::KeyUp { keycode: Some(Keycode::E), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_6] = false;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::SPEED_DOWN_KEY | Keycode::KpMinus), .. } => {
+                    run_control.speed_down();
+                    osd.show_message(&format!("speed {} ips", run_control.instructions_per_second));
+                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
                 }
-                Event::KeyUp { keycode: Some(Keycode::R), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_D] = false;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::SPEED_UP_KEY | Keycode::KpPlus), .. } => {
+                    run_control.speed_up();
+                    osd.show_message(&format!("speed {} ips", run_control.instructions_per_second));
+                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
                 }
-                Event::KeyUp { keycode: Some(Keycode::A), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_7] = false;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::CYCLE_PALETTE_KEY), repeat: false, .. } => {
+                    renderer.cycle_palette();
+                    osd.show_message(&format!("palette: {}", renderer.palette().name));
+                    chip8.draw_flag = true;
                 }
-                Event::KeyUp { keycode: Some(Keycode::S), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_8] = false;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::SCREENSHOT_KEY), repeat: false, .. } => {
+                    let result = capture::capture_path("screenshot", "png").and_then(|path| {
+                        capture::save_screenshot(&path, &chip8.frame_buffer, renderer.palette(), capture_scale)?;
+                        Ok(path)
+                    });
+                    match result {
+                        Ok(path) => {
+                            println!("saved screenshot {}", path.display());
+                            osd.show_message("screenshot saved");
+                        }
+                        Err(error) => eprintln!("{}", error),
+                    }
                 }
-                Event::KeyUp { keycode: Some(Keycode::D), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_9] = false;
+
+                Event::KeyDown { keycode: Some(EmulatorConfig::RECORD_KEY), repeat: false, .. } => {
+                    match recorder.take() {
+                        Some(active) => {
+                            finish_recording(active);
+                            osd.show_message("recording saved");
+                        }
+                        None => {
+                            let result = capture::capture_path("recording", "gif").and_then(|path| {
+                                Recorder::start(&path, renderer.palette(), capture_scale, None)
+                            });
+                            match result {
+                                Ok(started) => {
+                                    println!("recording to {}", started.path().display());
+                                    osd.show_message("recording");
+                                    recorder = Some(started);
+                                }
+                                Err(error) => eprintln!("{}", error),
+                            }
+                        }
+                    }
                 }
-                Event::KeyUp { keycode: Some(Keycode::F), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_E] = false;
+
+                _ => {
+                    let previous_keys = chip8.keyboard_keys;
+                    chip8.read_input(&event);
+                    instrumentation.keys_changed(&mut chip8, previous_keys);
                 }
-                Event::KeyUp { keycode: Some(Keycode::Z), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_A] = false;
+            }
+        }
+        if let Some(active_watcher) = watcher.as_mut() {
+            if active_watcher.changed() {
+                let path = active_watcher.path().to_path_buf();
+                let held_keys = chip8.keyboard_keys;
+                let quirks = chip8.quirks;
+                match start_rom(&mut chip8, &path, &database) {
+                    Ok(settings) => {
+                        current_rom_name = rom_name(&path, &settings);
+                        instrumentation.rom_started(&mut chip8, &current_rom_name);
+                        cheats.rom_started(&chip8);
+                        if keep_on_reload {
+                            chip8.keyboard_keys = held_keys;
+                            chip8.quirks = quirks;
+                        }
+                        else {
+                            apply_rom_settings(settings, &mut run_control, &mut renderer, &palette);
+                        }
+                        reload_count += 1;
+                        osd.show_message("rom reloaded");
+                        update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
+                    }
+                    Err(error) => eprintln!("{}", error),
                 }
-                Event::KeyUp { keycode: Some(Keycode::X), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_0] = false;
+            }
+        }
+        if let Some(active_console) = console.as_ref() {
+            while let Some(line) = active_console.poll() {
+                match run_console_command(&line, &mut chip8, &mut cheats, &mut instrumentation) {
+                    Ok(output) if output.is_empty() => {}
+                    Ok(output) => println!("{}", output),
+                    Err(error) => eprintln!("{}", error),
                 }
-                Event::KeyUp { keycode: Some(Keycode::C), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_B] = false;
+            }
+        }
+        if let Some(gdb) = instrumentation.gdb.as_mut() {
+            match gdb.poll(&mut chip8) {
+                GdbCommand::None => {}
+                GdbCommand::Halt => {
+                    run_control.paused = true;
+                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
                 }
-                Event::KeyUp { keycode: Some(Keycode::V), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_F] = false;
+                GdbCommand::Continue => {
+                    run_control.paused = false;
+                    update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
                 }
-                Event::KeyUp { keycode: Some(Keycode::Num1), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_1] = false;
+                GdbCommand::Step => {
+                    instrumentation.step(&mut chip8);
+                    instrumentation.gdb.as_mut().unwrap().step_done();
                 }
-                Event::KeyUp { keycode: Some(Keycode::Num2), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_2] = false;
+                GdbCommand::Kill => {
+                    running = false;
                 }
-                Event::KeyUp { keycode: Some(Keycode::Num3), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_3] = false;
+            }
+        }
+        if menu.is_none() {
+            if run_control.paused {
+                if advance_frame {
+                    run_frame(&mut chip8, &mut run_control, &mut osd, &mut instrumentation);
+                    cheats.apply(&mut chip8);
+                    chip8.draw_flag = true;
+                    advance_frame = false;
                 }
-                Event::KeyUp { keycode: Some(Keycode::Num4), .. } => {
-                    self.keyboard_keys[ChipKeyboard::CHIP_KEY_C] = false;
+            }
+            else {
+                for _ in 0..frames_due * run_control.frames_per_tick() {
+                    run_frame(&mut chip8, &mut run_control, &mut osd, &mut instrumentation);
+                    cheats.apply(&mut chip8);
+                    if instrumentation.breakpoint_hit() || chip8.fault.is_some() {
+                        break;
+                    }
                 }
+            }
+        }
+        if instrumentation.take_breakpoint_hit() {
+            run_control.paused = true;
+            let location = instrumentation.symbols.describe(chip8.PC);
+            println!("breakpoint at {:03X} {}", chip8.PC, location);
+            osd.show_message(&format!("breakpoint at {}", location));
+            update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
+        }
+        if let Some(problem) = instrumentation.stack_problem.take() {
+            run_control.paused = true;
+            eprintln!("{}\n{}", problem, callstack::backtrace(&chip8, &instrumentation.symbols));
+            if let Some(gdb) = instrumentation.gdb.as_mut() {
+                gdb.report_stop();
+            }
+            osd.show_message(problem.split(':').next().unwrap_or(&problem));
+            update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
+        }
+        if let Some(fault) = chip8.fault.take() {
+            run_control.paused = true;
+            eprintln!("{}\n{}", fault, callstack::backtrace(&chip8, &instrumentation.symbols));
+            if let Some(gdb) = instrumentation.gdb.as_mut() {
+                gdb.report_stop();
+            }
+            osd.show_message(fault.split(':').next().unwrap_or(&fault));
+            update_title(&mut canvas, &current_rom_name, &run_control, reload_count);
+        }
 
-                _ => {
+        if let Some(active_menu) = menu.as_mut() {
+            active_menu.draw(&mut canvas, renderer.palette());
+        }
+        else if chip8.draw_flag || renderer.needs_redraw() || osd.is_visible(run_control.paused)
+            || pacer.mode() == PacingMode::Vsync {
+            chip8.draw_graphics(&mut canvas, &mut renderer);
+            osd.draw(&mut canvas, renderer.palette(), run_control.paused);
+            canvas.present();
+        }
+        if let Some(viewer) = memory_viewer.as_mut() {
+            viewer.draw(&chip8, run_control.paused, &instrumentation.symbols);
+        }
+
+        if frames_due > 0 {
+            if let Some(active) = recorder.as_mut() {
+                if let Err(error) = active.add_frame(&chip8.frame_buffer) {
+                    eprintln!("{}", error);
+                    recorder = None;
+                }
+                else if active.is_done() {
+                    finish_recording(recorder.take().unwrap());
                 }
             }
         }
     }
-}
 
-fn main() {
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("chip chip chapa chapa 8", 
-            EmulatorConfig::SCREEN_WIDTH * EmulatorConfig::SCREEN_SCALE, 
-            EmulatorConfig::SCREEN_HEIGHT * EmulatorConfig::SCREEN_SCALE) 
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-
-    let mut event_pump = sdl_context.event_pump().unwrap();
-
-    let mut chip8: ChipContext = ChipContext::reset();
-    chip8.load_program("../roms/pong.rom");
-
-    let mut running: bool = true;
-    let mut start: Instant = Instant::now();
-
-    while running{
-        chip8.read_input(event_pump.poll_iter(), &mut running);
-        if start.elapsed().as_nanos() >= 1_000_000_000 / EmulatorConfig::INSTRUCTIONS_PER_SECOND  { 
-            chip8.exec_opcode();
-            chip8.update_timers();
-            if chip8.draw_flag {
-                chip8.draw_graphics(&mut canvas);
-            }
-            start = Instant::now();
+    if let Some(active) = recorder {
+        finish_recording(active);
+    }
+    if let (Some(profiler), Some(path)) = (&instrumentation.profiler, &profile_path) {
+        match profiler.save_report(path, &instrumentation.symbols) {
+            Ok(()) => println!("saved profile {}", path.display()),
+            Err(error) => eprintln!("{}", error),
         }
     }
 }
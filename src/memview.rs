@@ -0,0 +1,342 @@
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseWheelDirection;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+use crate::callstack;
+use crate::symbols::SymbolMap;
+use crate::{font, ChipContext, EmulatorConfig};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Memory,
+    Registers,
+}
+
+/* Second window with a hex and ASCII dump of the memory and the registers,
+ * both can be edited by typing hex digits while the emulator is paused
+ */
+pub struct MemoryViewer {
+    canvas: Canvas<Window>,
+    focus: Focus,
+    // Address of the first byte of the top row
+    top: usize,
+    cursor: usize,
+    // Set after the high nibble of the byte under the cursor was typed
+    low_nibble: bool,
+    register: usize,
+}
+
+impl MemoryViewer {
+    const SCALE: u32 = 2;
+    const MARGIN: i32 = 8;
+    const BYTES_PER_ROW: usize = 16;
+    const ROWS: usize = 32;
+    const PAGE: usize = Self::BYTES_PER_ROW * Self::ROWS;
+    // Register lines, status line and an empty line above the dump
+    const HEADER_LINES: usize = 5;
+    const LINE_CHARACTERS: u32 = 108;
+    // Columns of the first byte and first ASCII character of a row
+    const BYTES_COLUMN: usize = 6;
+    const ASCII_COLUMN: usize = Self::BYTES_COLUMN + 3 * Self::BYTES_PER_ROW + 1;
+    // The call stack is shown right of the dump
+    const STACK_COLUMN: usize = Self::ASCII_COLUMN + Self::BYTES_PER_ROW + 4;
+    const STACK_WIDTH: usize = Self::LINE_CHARACTERS as usize - Self::STACK_COLUMN;
+
+
+    const BACKGROUND: Color = Color::RGB(16, 16, 16);
+    const TEXT: Color = Color::RGB(200, 200, 200);
+    const DIM_TEXT: Color = Color::RGB(110, 110, 110);
+    const CURSOR: Color = Color::RGB(230, 230, 230);
+    const PC_HIGHLIGHT: Color = Color::RGB(150, 40, 40);
+    const I_HIGHLIGHT: Color = Color::RGB(40, 70, 150);
+    const FONT_HIGHLIGHT: Color = Color::RGB(60, 40, 80);
+    const ROM_HIGHLIGHT: Color = Color::RGB(30, 55, 30);
+
+    // PC, I, SP, DT, ST on the first line, V0-VF on the next two
+    const REGISTER_COUNT: usize = 21;
+    const REGISTER_LINE_BREAKS: [usize; 2] = [5, 13];
+
+    pub fn open(video_subsystem: &VideoSubsystem) -> Result<MemoryViewer, String> {
+        let width = 2 * Self::MARGIN as u32 + Self::LINE_CHARACTERS * font::ADVANCE * Self::SCALE;
+        let height = 2 * Self::MARGIN as u32 + (Self::HEADER_LINES + Self::ROWS) as u32 * Self::line_height() as u32;
+        let window = video_subsystem
+            .window(&format!("{} - memory", EmulatorConfig::WINDOW_TITLE), width, height)
+            .build()
+            .map_err(|error| error.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|error| error.to_string())?;
+        Ok(MemoryViewer {
+            canvas,
+            focus: Focus::Memory,
+            top: ChipContext::PROGRAM_START as usize,
+            cursor: ChipContext::PROGRAM_START as usize,
+            low_nibble: false,
+            register: 0,
+        })
+    }
+
+    pub fn window_id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    fn line_height() -> i32 {
+        ((font::GLYPH_HEIGHT + 3) * Self::SCALE) as i32
+    }
+
+    fn register_name(index: usize) -> String {
+        match index {
+            0 => String::from("PC"),
+            1 => String::from("I"),
+            2 => String::from("SP"),
+            3 => String::from("DT"),
+            4 => String::from("ST"),
+            _ => format!("V{:X}", index - 5),
+        }
+    }
+
+    /* (value, digits, mask) of the register at index */
+    fn register_value(chip8: &ChipContext, index: usize) -> (u16, usize, u16) {
+        match index {
+            0 => (chip8.PC, 3, 0xFFF),
+            1 => (chip8.I, 3, 0xFFF),
+            2 => (chip8.SP as u16, 1, 0xF),
+            3 => (chip8.delay_reg as u16, 2, 0xFF),
+            4 => (chip8.sound_reg as u16, 2, 0xFF),
+            _ => (chip8.registers[index - 5] as u16, 2, 0xFF),
+        }
+    }
+
+    fn set_register(chip8: &mut ChipContext, index: usize, value: u16) {
+        match index {
+            // Typed digits can reach 0xFFF, where the next fetch would read past memory
+            0 => chip8.PC = value.min(ChipContext::MAX_PC),
+            1 => chip8.I = value,
+            2 => chip8.SP = value as u8,
+            3 => chip8.delay_reg = value as u8,
+            4 => chip8.sound_reg = value as u8,
+            _ => chip8.registers[index - 5] = value as u8,
+        }
+    }
+
+    fn move_cursor(&mut self, offset: isize) {
+        self.cursor = (self.cursor as isize + offset).clamp(0, 4095) as usize;
+        self.low_nibble = false;
+        if self.cursor < self.top {
+            self.top = self.cursor - self.cursor % Self::BYTES_PER_ROW;
+        }
+        else if self.cursor >= self.top + Self::PAGE {
+            self.top = self.cursor - self.cursor % Self::BYTES_PER_ROW + Self::BYTES_PER_ROW - Self::PAGE;
+        }
+    }
+
+    fn scroll(&mut self, rows: isize) {
+        let last_top = 4096 - Self::PAGE;
+        self.top = (self.top as isize + rows * Self::BYTES_PER_ROW as isize).clamp(0, last_top as isize) as usize;
+    }
+
+    fn hex_digit(keycode: Keycode) -> Option<u8> {
+        let name = keycode.name();
+        if name.len() == 1 {
+            u8::from_str_radix(&name, 16).ok()
+        }
+        else {
+            None
+        }
+    }
+
+    fn type_digit(&mut self, chip8: &mut ChipContext, digit: u8) {
+        match self.focus {
+            Focus::Memory => {
+                let byte = &mut chip8.memory[self.cursor];
+                if self.low_nibble {
+                    *byte = (*byte & 0xF0) | digit;
+                    self.move_cursor(1);
+                }
+                else {
+                    *byte = (*byte & 0x0F) | digit << 4;
+                    self.low_nibble = true;
+                }
+            }
+            Focus::Registers => {
+                // Digits shift in from the right like on a calculator
+                let (value, _, mask) = Self::register_value(chip8, self.register);
+                Self::set_register(chip8, self.register, (value << 4 | digit as u16) & mask);
+            }
+        }
+    }
+
+    /* Handles an event for this window, false once it should be closed */
+    pub fn handle_event(&mut self, event: &Event, chip8: &mut ChipContext, paused: bool) -> bool {
+        match event {
+            Event::Window { win_event: WindowEvent::Close, .. } |
+            Event::KeyDown { keycode: Some(Keycode::Escape | EmulatorConfig::MEMORY_VIEWER_KEY), .. } => {
+                return false;
+            }
+
+            Event::MouseWheel { y, direction, .. } => {
+                let rows = if *direction == MouseWheelDirection::Flipped { *y } else { -*y };
+                self.scroll(3 * rows as isize);
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::Tab), .. } => {
+                self.focus = match self.focus {
+                    Focus::Memory => Focus::Registers,
+                    Focus::Registers => Focus::Memory,
+                };
+                self.low_nibble = false;
+            }
+
+            Event::KeyDown { keycode: Some(Keycode::Home), .. } => {
+                let pc = chip8.PC as isize;
+                self.move_cursor(pc - self.cursor as isize);
+            }
+
+            Event::KeyDown { keycode: Some(keycode), .. } if self.focus == Focus::Registers => {
+                match *keycode {
+                    Keycode::Left => self.register = (self.register + Self::REGISTER_COUNT - 1) % Self::REGISTER_COUNT,
+                    Keycode::Right => self.register = (self.register + 1) % Self::REGISTER_COUNT,
+                    _ => {
+                        if let (Some(digit), true) = (Self::hex_digit(*keycode), paused) {
+                            self.type_digit(chip8, digit);
+                        }
+                    }
+                }
+            }
+
+            Event::KeyDown { keycode: Some(keycode), .. } => {
+                match *keycode {
+                    Keycode::Left => self.move_cursor(-1),
+                    Keycode::Right => self.move_cursor(1),
+                    Keycode::Up => self.move_cursor(-(Self::BYTES_PER_ROW as isize)),
+                    Keycode::Down => self.move_cursor(Self::BYTES_PER_ROW as isize),
+                    Keycode::PageUp => self.move_cursor(-(Self::PAGE as isize)),
+                    Keycode::PageDown => self.move_cursor(Self::PAGE as isize),
+                    _ => {
+                        if let (Some(digit), true) = (Self::hex_digit(*keycode), paused) {
+                            self.type_digit(chip8, digit);
+                        }
+                    }
+                }
+            }
+
+            _ => {}
+        }
+        true
+    }
+
+    fn column_x(column: usize) -> i32 {
+        Self::MARGIN + (column as u32 * font::ADVANCE * Self::SCALE) as i32
+    }
+
+    fn line_y(line: usize) -> i32 {
+        Self::MARGIN + line as i32 * Self::line_height()
+    }
+
+    /* Background box behind characters columns..columns + width of a line */
+    fn highlight(&mut self, column: usize, line: usize, width: usize, color: Color) {
+        let padding = Self::SCALE as i32;
+        self.canvas.set_draw_color(color);
+        let _ = self.canvas.fill_rect(Rect::new(
+            Self::column_x(column) - padding,
+            Self::line_y(line) - padding,
+            width as u32 * font::ADVANCE * Self::SCALE + padding as u32,
+            Self::line_height() as u32
+            ));
+    }
+
+    fn byte_highlight(&self, chip8: &ChipContext, address: usize) -> Option<Color> {
+        let pc = chip8.PC as usize;
+        let i = chip8.I as usize;
+        let program_start = ChipContext::PROGRAM_START as usize;
+        let program_end = program_start + chip8.program_size;
+        if address == pc || address == pc + 1 {
+            Some(Self::PC_HIGHLIGHT)
+        }
+        else if address == i {
+            Some(Self::I_HIGHLIGHT)
+        }
+        else if (ChipContext::FONT_START as usize..ChipContext::FONT_END as usize).contains(&address) {
+            Some(Self::FONT_HIGHLIGHT)
+        }
+        else if (program_start..program_end).contains(&address) {
+            Some(Self::ROM_HIGHLIGHT)
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn draw(&mut self, chip8: &ChipContext, paused: bool, symbols: &SymbolMap) {
+        self.canvas.set_draw_color(Self::BACKGROUND);
+        self.canvas.clear();
+
+        let mut line = 0;
+        let mut column = 0;
+        for index in 0..Self::REGISTER_COUNT {
+            if Self::REGISTER_LINE_BREAKS.contains(&index) {
+                line += 1;
+                column = 0;
+            }
+            let name = Self::register_name(index);
+            let (value, digits, _) = Self::register_value(chip8, index);
+            let value = format!("{:01$X}", value, digits);
+            let value_column = column + name.len() + 1;
+            let mut color = Self::TEXT;
+            if self.focus == Focus::Registers && index == self.register {
+                self.highlight(value_column, line, digits, Self::CURSOR);
+                color = Self::BACKGROUND;
+            }
+            font::draw_text(&mut self.canvas, &name, Self::column_x(column), Self::line_y(line), Self::SCALE, Self::DIM_TEXT);
+            font::draw_text(&mut self.canvas, &value, Self::column_x(value_column), Self::line_y(line), Self::SCALE, color);
+            column = value_column + digits + 3;
+        }
+
+        let status = match (paused, self.focus) {
+            (false, _) => "RUNNING, PAUSE TO EDIT",
+            (true, Focus::Memory) => "TYPE HEX TO EDIT, TAB: REGISTERS, HOME: GO TO PC",
+            (true, Focus::Registers) => "TYPE HEX TO EDIT, TAB: MEMORY, LEFT/RIGHT: SELECT",
+        };
+        font::draw_text(&mut self.canvas, status, Self::column_x(0), Self::line_y(3), Self::SCALE, Self::DIM_TEXT);
+
+        for row in 0..Self::ROWS {
+            let line = Self::HEADER_LINES + row;
+            let row_address = self.top + row * Self::BYTES_PER_ROW;
+            font::draw_text(&mut self.canvas, &format!("{:04X}", row_address), Self::column_x(0), Self::line_y(line),
+                Self::SCALE, Self::DIM_TEXT);
+
+            let mut ascii = String::new();
+            for offset in 0..Self::BYTES_PER_ROW {
+                let address = row_address + offset;
+                let byte = chip8.memory[address];
+                let byte_column = Self::BYTES_COLUMN + 3 * offset;
+                let mut color = Self::TEXT;
+                if self.focus == Focus::Memory && address == self.cursor {
+                    self.highlight(byte_column, line, 2, Self::CURSOR);
+                    color = Self::BACKGROUND;
+                }
+                else if let Some(highlight) = self.byte_highlight(chip8, address) {
+                    self.highlight(byte_column, line, 2, highlight);
+                }
+                font::draw_text(&mut self.canvas, &format!("{:02X}", byte), Self::column_x(byte_column), Self::line_y(line),
+                    Self::SCALE, color);
+                ascii.push(if (0x20..0x7F).contains(&byte) { byte as char } else { '.' });
+            }
+            font::draw_text(&mut self.canvas, &ascii, Self::column_x(Self::ASCII_COLUMN), Self::line_y(line),
+                Self::SCALE, Self::DIM_TEXT);
+        }
+
+        font::draw_text(&mut self.canvas, "CALL STACK", Self::column_x(Self::STACK_COLUMN), Self::line_y(Self::HEADER_LINES),
+            Self::SCALE, Self::DIM_TEXT);
+        for (index, frame) in callstack::frames(chip8, symbols).iter().enumerate() {
+            let frame: String = frame.chars().take(Self::STACK_WIDTH).collect();
+            font::draw_text(&mut self.canvas, &frame, Self::column_x(Self::STACK_COLUMN), Self::line_y(Self::HEADER_LINES + 1 + index),
+                Self::SCALE, Self::TEXT);
+        }
+
+        self.canvas.present();
+    }
+}
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use sdl2::keyboard::Keycode;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+
+use crate::font;
+use crate::palette::Palette;
+use crate::rom::{self, Platform};
+use crate::source;
+
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: usize,
+    pub platform: Platform,
+}
+
+pub enum MenuAction {
+    None,
+    Launch(PathBuf),
+}
+
+/* In-window list of the ROMs found in a directory */
+pub struct RomMenu {
+    directory: PathBuf,
+    entries: Vec<RomEntry>,
+    selected: usize,
+    scroll: usize,
+    error: Option<String>,
+}
+
+impl RomMenu {
+    const TEXT_SCALE: u32 = 2;
+    const MARGIN: i32 = 16;
+
+    pub fn scan(directory: &Path) -> RomMenu {
+        let mut menu = RomMenu {
+            directory: directory.to_path_buf(),
+            entries: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            error: None,
+        };
+
+        match std::fs::read_dir(directory) {
+            Ok(read_dir) => {
+                for dir_entry in read_dir.flatten() {
+                    let path = dir_entry.path();
+                    if !path.is_file() {
+                        continue;
+                    }
+                    let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+                    // An archive holding several ROMs gets an entry for each, loaded as ARCHIVE/NAME
+                    let roms = source::archive_roms(&path);
+                    let candidates = if roms.len() > 1 {
+                        roms.iter().map(|rom| (path.join(rom), format!("{}/{}", file_name, rom))).collect()
+                    }
+                    else {
+                        vec![(path, file_name)]
+                    };
+                    for (path, name) in candidates {
+                        let Ok(bytes) = source::read(&path) else {
+                            continue;
+                        };
+                        menu.entries.push(RomEntry {
+                            name,
+                            size: bytes.len(),
+                            platform: rom::detect_platform(&bytes),
+                            path,
+                        });
+                    }
+                }
+                menu.entries.sort_by_key(|entry| entry.name.to_lowercase());
+                if menu.entries.is_empty() {
+                    menu.error = Some(format!("no ROMs in {}", directory.display()));
+                }
+            }
+            Err(error) => {
+                menu.error = Some(format!("cannot read {}: {}", directory.display(), error));
+            }
+        }
+        menu
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn handle_key(&mut self, keycode: Keycode) -> MenuAction {
+        if self.entries.is_empty() {
+            return MenuAction::None;
+        }
+
+        let last = self.entries.len() - 1;
+        match keycode {
+            Keycode::Up => self.selected = self.selected.saturating_sub(1),
+            Keycode::Down => self.selected = (self.selected + 1).min(last),
+            Keycode::PageUp => self.selected = self.selected.saturating_sub(10),
+            Keycode::PageDown => self.selected = (self.selected + 10).min(last),
+            Keycode::Home => self.selected = 0,
+            Keycode::End => self.selected = last,
+            Keycode::Return | Keycode::KpEnter => {
+                self.error = None;
+                return MenuAction::Launch(self.entries[self.selected].path.clone());
+            }
+            _ => {}
+        }
+        MenuAction::None
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, palette: &Palette) {
+        let background = palette.colors[0];
+        let foreground = palette.colors[1];
+        let line_height = ((font::GLYPH_HEIGHT + 3) * Self::TEXT_SCALE) as i32;
+        let (output_width, output_height) = canvas.output_size().unwrap();
+
+        canvas.set_draw_color(background);
+        canvas.clear();
+
+        let title = format!("ROMS IN {}", self.directory.display());
+        font::draw_text(canvas, &title, Self::MARGIN, Self::MARGIN, Self::TEXT_SCALE, foreground);
+
+        let list_top = Self::MARGIN + 2 * line_height;
+        let list_bottom = output_height as i32 - Self::MARGIN - 2 * line_height;
+        let visible_rows = ((list_bottom - list_top) / line_height).max(1) as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        }
+        if self.selected >= self.scroll + visible_rows {
+            self.scroll = self.selected + 1 - visible_rows;
+        }
+
+        for (row, entry) in self.entries.iter().enumerate().skip(self.scroll).take(visible_rows) {
+            let y = list_top + (row - self.scroll) as i32 * line_height;
+            let mut color = foreground;
+            if row == self.selected {
+                canvas.set_draw_color(foreground);
+                let _ = canvas.fill_rect(Rect::new(
+                    Self::MARGIN / 2,
+                    y - Self::TEXT_SCALE as i32,
+                    output_width.saturating_sub(Self::MARGIN as u32),
+                    line_height as u32
+                    ));
+                color = background;
+            }
+
+            let details = format!("{} B  {}", entry.size, entry.platform.name());
+            let details_x = output_width as i32 - Self::MARGIN - font::text_width(&details, Self::TEXT_SCALE) as i32;
+            font::draw_text(canvas, &entry.name, Self::MARGIN, y, Self::TEXT_SCALE, color);
+            font::draw_text(canvas, &details, details_x, y, Self::TEXT_SCALE, color);
+        }
+
+        let footer = match &self.error {
+            Some(error) => error.clone(),
+            None => String::from("UP/DOWN select  ENTER run  F1 back to menu  ESC quit"),
+        };
+        font::draw_text(canvas, &footer, Self::MARGIN, output_height as i32 - Self::MARGIN - line_height, Self::TEXT_SCALE, foreground);
+
+        canvas.present();
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashSet;
+
+use crate::symbols::SymbolMap;
+use crate::{get_byte_0x000F, get_byte_0x0F00, get_byte_0xF000, get_bytes_0x00FF, ChipContext};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Origin {
+    Uninitialised,
+    Font,
+    Rom,
+    // Stored by Fx33/Fx55 at this PC
+    Runtime(u16),
+}
+
+/* Remembers where every memory byte came from and warns about executing
+ * bytes written at runtime and reading bytes nothing ever wrote
+ */
+pub struct MemoryOrigins {
+    origins: Vec<Origin>,
+    // (PC, address) pairs already warned about, every problem is reported once
+    warned: HashSet<(u16, u16)>,
+}
+
+impl MemoryOrigins {
+    pub fn new() -> MemoryOrigins {
+        MemoryOrigins {
+            origins: vec![Origin::Uninitialised; 4096],
+            warned: HashSet::new(),
+        }
+    }
+
+    pub fn rom_started(&mut self, chip8: &ChipContext) {
+        self.origins.fill(Origin::Uninitialised);
+        self.origins[ChipContext::FONT_START as usize..ChipContext::FONT_END as usize].fill(Origin::Font);
+        let program_start = ChipContext::PROGRAM_START as usize;
+        self.origins[program_start..program_start + chip8.program_size].fill(Origin::Rom);
+        self.warned.clear();
+    }
+
+    fn warn(&mut self, pc: u16, address: u16, message: String) {
+        if self.warned.insert((pc, address)) {
+            eprintln!("warning: {}", message);
+        }
+    }
+
+    fn check_read(&mut self, chip8: &ChipContext, symbols: &SymbolMap, start: u16, length: u16, instruction: &str) {
+        for address in (0..length).map(|offset| start.wrapping_add(offset) & 0xFFF) {
+            if self.origins[address as usize] == Origin::Uninitialised {
+                self.warn(chip8.PC, address, format!("{} at {:03X} ({}) reads uninitialised memory at {:03X} (I = {:03X})",
+                    instruction, chip8.PC, symbols.describe(chip8.PC), address, chip8.I));
+                return;
+            }
+        }
+    }
+
+    /* Call before the instruction at PC runs */
+    pub fn before_instruction(&mut self, chip8: &ChipContext, symbols: &SymbolMap) {
+        let pc = chip8.PC;
+        for address in [pc & 0xFFF, (pc + 1) & 0xFFF] {
+            match self.origins[address as usize] {
+                Origin::Runtime(writer) => {
+                    self.warn(pc, address, format!("executing {:03X} ({}), written at runtime by the instruction at {:03X} ({})",
+                        pc, symbols.describe(pc), writer, symbols.describe(writer)));
+                    break;
+                }
+                Origin::Uninitialised => {
+                    self.warn(pc, address, format!("executing uninitialised memory at {:03X} ({})", pc, symbols.describe(pc)));
+                    break;
+                }
+                Origin::Font | Origin::Rom => {}
+            }
+        }
+
+        let opcode = chip8.fetch_opcode();
+        match (get_byte_0xF000(opcode), get_bytes_0x00FF(opcode)) {
+            (0xD, _) => self.check_read(chip8, symbols, chip8.I, get_byte_0x000F(opcode), "drw"),
+            (0xF, 0x65) => self.check_read(chip8, symbols, chip8.I, get_byte_0x0F00(opcode) + 1, "ld Vx, [i]"),
+            _ => {}
+        }
+    }
+
+    /* Call after the instruction ran, with the opcode and I from before it */
+    pub fn after_instruction(&mut self, chip8: &ChipContext, pc: u16, opcode: u16, i: u16) {
+        for (address, _) in chip8.memory_writes(opcode, i) {
+            self.origins[address as usize] = Origin::Runtime(pc);
+        }
+    }
+}
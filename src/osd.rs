@@ -0,0 +1,123 @@
+use std::time::{Duration, Instant};
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::Window;
+
+use crate::font;
+use crate::palette::Palette;
+
+/* Overlay drawn over the CHIP-8 display: transient messages, an optional
+ * FPS/IPS counter and the pause banner
+ */
+pub struct Osd {
+    messages: Vec<(String, Instant)>,
+    show_stats: bool,
+    frames: u32,
+    instructions: u32,
+    counting_since: Instant,
+    frames_per_second: u32,
+    instructions_per_second: u32,
+}
+
+impl Osd {
+    const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+    const MAX_MESSAGES: usize = 4;
+    const BOX_ALPHA: u8 = 180;
+
+    pub fn new() -> Osd {
+        Osd {
+            messages: Vec::new(),
+            show_stats: false,
+            frames: 0,
+            instructions: 0,
+            counting_since: Instant::now(),
+            frames_per_second: 0,
+            instructions_per_second: 0,
+        }
+    }
+
+    pub fn show_message(&mut self, message: &str) {
+        self.messages.push((message.to_uppercase(), Instant::now()));
+        if self.messages.len() > Self::MAX_MESSAGES {
+            self.messages.remove(0);
+        }
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    pub fn count_instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    pub fn count_frame(&mut self) {
+        self.frames += 1;
+        let elapsed = self.counting_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.frames_per_second = (self.frames as f32 / elapsed.as_secs_f32()).round() as u32;
+            self.instructions_per_second = (self.instructions as f32 / elapsed.as_secs_f32()).round() as u32;
+            self.frames = 0;
+            self.instructions = 0;
+            self.counting_since = Instant::now();
+        }
+    }
+
+    /* True while something is on screen, the display then has to be
+     * redrawn every frame even if the framebuffer didn't change
+     */
+    pub fn is_visible(&self, paused: bool) -> bool {
+        paused || self.show_stats || !self.messages.is_empty()
+    }
+
+    fn text_scale(output_height: u32) -> u32 {
+        (output_height / 160).max(1)
+    }
+
+    fn draw_boxed_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, scale: u32, palette: &Palette) {
+        let padding = scale as i32 * 2;
+        let background = palette.colors[0];
+        canvas.set_draw_color(Color::RGBA(background.r, background.g, background.b, Self::BOX_ALPHA));
+        let _ = canvas.fill_rect(Rect::new(
+            x - padding,
+            y - padding,
+            font::text_width(text, scale) + 2 * padding as u32,
+            font::GLYPH_HEIGHT * scale + 2 * padding as u32
+            ));
+        font::draw_text(canvas, text, x, y, scale, palette.colors[1]);
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas<Window>, palette: &Palette, paused: bool) {
+        self.messages.retain(|(_, shown)| shown.elapsed() < Self::MESSAGE_DURATION);
+
+        let (output_width, output_height) = canvas.output_size().unwrap();
+        let scale = Self::text_scale(output_height);
+        let margin = 4 * scale as i32;
+        let line_height = ((font::GLYPH_HEIGHT + 5) * scale) as i32;
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        if self.show_stats {
+            let stats = format!("{} FPS {} IPS", self.frames_per_second, self.instructions_per_second);
+            let x = output_width as i32 - margin - font::text_width(&stats, scale) as i32;
+            Self::draw_boxed_text(canvas, &stats, x, margin, scale, palette);
+        }
+
+        if paused {
+            let banner = "PAUSED";
+            let banner_scale = scale * 2;
+            let x = (output_width as i32 - font::text_width(banner, banner_scale) as i32) / 2;
+            let y = (output_height as i32 - (font::GLYPH_HEIGHT * banner_scale) as i32) / 2;
+            Self::draw_boxed_text(canvas, banner, x, y, banner_scale, palette);
+        }
+
+        let mut y = output_height as i32 - margin - line_height * self.messages.len() as i32;
+        for (message, _) in &self.messages {
+            Self::draw_boxed_text(canvas, message, margin, y + 2 * scale as i32, scale, palette);
+            y += line_height;
+        }
+
+        canvas.set_blend_mode(BlendMode::None);
+    }
+}
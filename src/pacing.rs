@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PacingMode {
+    // Sleep until the next frame is due
+    Sleep,
+    // Let presenting block on the display refresh, frames are run as real time passes
+    Vsync,
+    // No waiting at all, for benchmarking
+    Uncapped,
+}
+
+/* Decides how many emulated frames are due each main loop iteration.
+ * Deadlines advance by exactly one frame time so sleeping too long is made
+ * up for by the next frame instead of slowly drifting.
+ */
+pub struct FramePacer {
+    mode: PacingMode,
+    frame_time: Duration,
+    next_deadline: Instant,
+}
+
+impl FramePacer {
+    // Further behind than this and the missed frames are dropped instead of caught up
+    const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+    pub fn new(mode: PacingMode, frame_time: Duration) -> FramePacer {
+        FramePacer {
+            mode,
+            frame_time,
+            next_deadline: Instant::now(),
+        }
+    }
+
+    pub fn mode(&self) -> PacingMode {
+        self.mode
+    }
+
+    pub fn wait_for_frames(&mut self) -> u32 {
+        if self.mode == PacingMode::Uncapped {
+            return 1;
+        }
+
+        let mut now = Instant::now();
+        if self.mode == PacingMode::Sleep && now < self.next_deadline {
+            std::thread::sleep(self.next_deadline - now);
+            now = Instant::now();
+        }
+
+        let mut frames_due = 0;
+        while now >= self.next_deadline && frames_due < Self::MAX_CATCH_UP_FRAMES {
+            self.next_deadline += self.frame_time;
+            frames_due += 1;
+        }
+        if now >= self.next_deadline {
+            self.next_deadline = now + self.frame_time;
+        }
+        frames_due
+    }
+}
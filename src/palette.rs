@@ -0,0 +1,72 @@
+use sdl2::pixels::Color;
+
+/* colors[0] is the background, colors[1] pixels lit on the first plane,
+ * colors[2] and colors[3] the second plane and both planes for XO-CHIP
+ */
+#[derive(Clone)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [Color; 4],
+}
+
+impl Palette {
+    const BUILT_IN: [(&'static str, [u32; 4]); 5] = [
+        ("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+        ("amber", [0x1A0F00, 0xFFB000, 0xB36B00, 0xFFD27F]),
+        ("green-phosphor", [0x0A1A0A, 0x33FF33, 0x1F991F, 0x99FF99]),
+        ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+        ("high-contrast", [0x000000, 0xFFFF00, 0x00FFFF, 0xFF00FF]),
+    ];
+
+    fn from_rgb(name: &str, colors: [u32; 4]) -> Palette {
+        Palette {
+            name: String::from(name),
+            colors: colors.map(|rgb| Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+        }
+    }
+
+    pub fn built_in() -> Vec<Palette> {
+        Self::BUILT_IN
+            .iter()
+            .map(|(name, colors)| Self::from_rgb(name, *colors))
+            .collect()
+    }
+
+    pub fn by_name(name: &str) -> Option<Palette> {
+        Self::built_in().into_iter().find(|palette| palette.name == name)
+    }
+
+    pub fn names() -> Vec<&'static str> {
+        Self::BUILT_IN.iter().map(|(name, _)| *name).collect()
+    }
+
+    /* Accepts "RRGGBB" or "#RRGGBB" */
+    pub fn parse_hex_color(text: &str) -> Option<Color> {
+        let digits = text.strip_prefix('#').unwrap_or(text);
+        if digits.len() != 6 {
+            return None;
+        }
+        let rgb = u32::from_str_radix(digits, 16).ok()?;
+        Some(Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+    }
+
+    /* Custom palette from a comma separated list of 2 to 4 hex colors,
+     * missing plane colors are taken from the base palette
+     */
+    pub fn parse_custom(text: &str, base: &Palette) -> Option<Palette> {
+        let colors: Vec<Color> = text
+            .split(',')
+            .map(|color| Self::parse_hex_color(color.trim()))
+            .collect::<Option<Vec<Color>>>()?;
+        if colors.len() < 2 || colors.len() > 4 {
+            return None;
+        }
+
+        let mut palette = Palette {
+            name: String::from("custom"),
+            colors: base.colors,
+        };
+        palette.colors[..colors.len()].copy_from_slice(&colors);
+        Some(palette)
+    }
+}
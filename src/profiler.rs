@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+use crate::disasm;
+use crate::symbols::SymbolMap;
+use crate::ChipContext;
+
+/* Counts executions per address and per opcode and follows call/ret pairs
+ * to build a call graph
+ */
+pub struct Profiler {
+    address_counts: Vec<u64>,
+    // Last opcode executed at every address, for the disassembly
+    address_opcodes: Vec<u16>,
+    opcode_counts: HashMap<u16, u64>,
+    // (caller subroutine, callee subroutine) -> calls
+    calls: HashMap<(u16, u16), u64>,
+    subroutines: Vec<u16>,
+    total: u64,
+}
+
+impl Profiler {
+    const TOP_ENTRIES: usize = 20;
+
+    pub fn new() -> Profiler {
+        Profiler {
+            address_counts: vec![0; 4096],
+            address_opcodes: vec![0; 4096],
+            opcode_counts: HashMap::new(),
+            calls: HashMap::new(),
+            subroutines: Vec::new(),
+            total: 0,
+        }
+    }
+
+    fn current_subroutine(&self) -> u16 {
+        *self.subroutines.last().unwrap_or(&ChipContext::PROGRAM_START)
+    }
+
+    /* Call before the instruction at PC runs */
+    pub fn record(&mut self, chip8: &ChipContext) {
+        let pc = chip8.PC as usize & 0xFFF;
+        let opcode = chip8.fetch_opcode();
+        self.address_counts[pc] += 1;
+        self.address_opcodes[pc] = opcode;
+        *self.opcode_counts.entry(opcode).or_insert(0) += 1;
+        self.total += 1;
+
+        if opcode & 0xF000 == 0x2000 {
+            let target = opcode & 0x0FFF;
+            *self.calls.entry((self.current_subroutine(), target)).or_insert(0) += 1;
+            self.subroutines.push(target);
+        }
+        else if opcode == 0x00EE {
+            self.subroutines.pop();
+        }
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        100.0 * count as f64 / self.total.max(1) as f64
+    }
+
+    fn label(address: u16, symbols: &SymbolMap) -> String {
+        if let Some(label) = symbols.label(address) {
+            String::from(label)
+        }
+        else if address == ChipContext::PROGRAM_START {
+            String::from("main")
+        }
+        else {
+            format!("sub_{:03X}", address)
+        }
+    }
+
+    pub fn report(&self, symbols: &SymbolMap) -> String {
+        let mut report = String::new();
+        let _ = writeln!(report, "{} instructions executed", self.total);
+
+        let _ = writeln!(report, "\n== top addresses ==");
+        let mut addresses: Vec<usize> = (0..self.address_counts.len()).filter(|address| self.address_counts[*address] > 0).collect();
+        addresses.sort_by_key(|address| std::cmp::Reverse(self.address_counts[*address]));
+        for address in addresses.iter().take(Self::TOP_ENTRIES) {
+            let count = self.address_counts[*address];
+            let opcode = self.address_opcodes[*address];
+            let _ = writeln!(report, "{:>12} {:>6.2}%  {:03X}  {:04X}  {}",
+                count, self.percent(count), address, opcode, disasm::disassemble_labeled(opcode, symbols));
+        }
+
+        let _ = writeln!(report, "\n== top opcodes ==");
+        let mut opcodes: Vec<(&u16, &u64)> = self.opcode_counts.iter().collect();
+        opcodes.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for (opcode, count) in opcodes.iter().take(Self::TOP_ENTRIES) {
+            let _ = writeln!(report, "{:>12} {:>6.2}%  {:04X}  {}", count, self.percent(**count), opcode, disasm::disassemble_labeled(**opcode, symbols));
+        }
+
+        let _ = writeln!(report, "\n== call graph ==");
+        let mut calls: Vec<(&(u16, u16), &u64)> = self.calls.iter().collect();
+        calls.sort();
+        for ((caller, callee), count) in calls {
+            let _ = writeln!(report, "{:>12}  {} -> {}", count, Self::label(*caller, symbols), Self::label(*callee, symbols));
+        }
+
+        let _ = writeln!(report, "\n== annotated disassembly ==");
+        addresses.sort();
+        let mut previous: Option<usize> = None;
+        for address in addresses {
+            if previous.is_some_and(|previous| address != previous + 2) {
+                let _ = writeln!(report, "{:>12}", "...");
+            }
+            let callee = address as u16;
+            if callee == ChipContext::PROGRAM_START || symbols.label(callee).is_some() || self.calls.keys().any(|(_, target)| *target == callee) {
+                let _ = writeln!(report, "{}:", Self::label(callee, symbols));
+            }
+            let count = self.address_counts[address];
+            let opcode = self.address_opcodes[address];
+            let _ = writeln!(report, "{:>12} {:>6.2}%  {:03X}  {:04X}  {}",
+                count, self.percent(count), address, opcode, disasm::disassemble_labeled(opcode, symbols));
+            previous = Some(address);
+        }
+        report
+    }
+
+    pub fn save_report(&self, path: &Path, symbols: &SymbolMap) -> Result<(), String> {
+        std::fs::write(path, self.report(symbols)).map_err(|error| format!("cannot write {}: {}", path.display(), error))
+    }
+}
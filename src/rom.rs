@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::disasm;
+use crate::{get_byte_0x000F, get_byte_0x00F0, get_byte_0x0F00, get_byte_0xF000, get_bytes_0x00FF, get_bytes_0x0FFF, ChipContext, Quirks};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SCHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+/* Room for a program between the program start and the end of the 4K memory */
+pub const MAX_ROM_SIZE: usize = 4096 - ChipContext::PROGRAM_START as usize;
+
+pub fn is_super_chip_opcode(opcode: u16) -> bool {
+    match get_byte_0xF000(opcode) {
+        // scd n, scr, scl, exit, low, high
+        0x0 => opcode & 0xFFF0 == 0x00C0 || (0x00FB..=0x00FF).contains(&opcode),
+        // ld hf, Vx / ld r, Vx / ld Vx, r
+        0xF => matches!(get_bytes_0x00FF(opcode), 0x30 | 0x75 | 0x85),
+        _ => false,
+    }
+}
+
+pub fn is_xo_chip_opcode(opcode: u16) -> bool {
+    match get_byte_0xF000(opcode) {
+        // scu n
+        0x0 => opcode & 0xFFF0 == 0x00D0,
+        // save Vx - Vy / load Vx - Vy
+        0x5 => matches!(get_byte_0x000F(opcode), 0x2 | 0x3),
+        // i := long nnnn, plane n, audio, pitch := Vx
+        0xF => opcode == 0xF000 || opcode == 0xF002
+            || (get_bytes_0x00FF(opcode) == 0x01 && get_byte_0x00F0(opcode) == 0)
+            || get_bytes_0x00FF(opcode) == 0x3A,
+        _ => false,
+    }
+}
+
+pub fn is_valid_opcode(opcode: u16) -> bool {
+    if is_xo_chip_opcode(opcode) || is_super_chip_opcode(opcode) {
+        return true;
+    }
+    match get_byte_0xF000(opcode) {
+        // 0nnn calls machine code, which no interpreter runs
+        0x0 => matches!(opcode, 0x00E0 | 0x00EE),
+        _ => !disasm::disassemble(opcode).starts_with("db"),
+    }
+}
+
+/* Addresses execution can go on to after the instruction at address, with
+ * the value of I there when it is known
+ */
+fn successors(chip8: &ChipContext, address: u16, opcode: u16, i: Option<u16>) -> Vec<(u16, Option<u16>)> {
+    let nnn = get_bytes_0x0FFF(opcode);
+    let next = address + 2;
+    match get_byte_0xF000(opcode) {
+        0x0 => match opcode {
+            // ret and SCHIP exit end the path
+            0x00EE | 0x00FD => Vec::new(),
+            _ => vec![(next, i)],
+        },
+        0x1 => vec![(nnn, i)],
+        // The subroutine may change I, don't assume it survives the call
+        0x2 => vec![(nnn, i), (next, None)],
+        0x3 | 0x4 | 0x5 | 0x9 | 0xE => {
+            // The XO-CHIP long load is 4 bytes, a skip jumps over all of it
+            let skipped = if chip8.opcode_at(next) == 0xF000 { next + 4 } else { next + 2 };
+            vec![(next, i), (skipped, i)]
+        }
+        0xA => vec![(next, Some(nnn))],
+        // Computed jumps aren't followed
+        0xB => Vec::new(),
+        0xF => match get_bytes_0x00FF(opcode) {
+            0x00 => vec![(next + 2, Some(chip8.opcode_at(next) & 0xFFF))],
+            // Whether I moves past the registers depends on the quirk, add i, Vx and ld f, Vx depend on a register
+            0x55 | 0x65 | 0x1E | 0x29 | 0x30 => vec![(next, None)],
+            _ => vec![(next, i)],
+        },
+        _ => vec![(next, i)],
+    }
+}
+
+/* Walks every instruction reachable from the program start, following
+ * jumps, calls and skips, so sprite data is never mistaken for code. Maps
+ * each reached address to the value of I there, None once it depends on
+ * the path or on registers. Addresses outside the ROM are included but
+ * not walked past, neither are invalid instructions.
+ */
+pub fn reachable_code(chip8: &ChipContext) -> BTreeMap<u16, Option<u16>> {
+    let program_end = ChipContext::PROGRAM_START + chip8.program_size as u16;
+    let mut reached: BTreeMap<u16, Option<u16>> = BTreeMap::new();
+    let mut queue = VecDeque::from([(ChipContext::PROGRAM_START, None)]);
+    reached.insert(ChipContext::PROGRAM_START, None);
+
+    while let Some((address, i)) = queue.pop_front() {
+        let opcode = chip8.opcode_at(address);
+        if address < ChipContext::PROGRAM_START || address >= program_end || !is_valid_opcode(opcode) {
+            continue;
+        }
+        for (next, next_i) in successors(chip8, address, opcode, i) {
+            let state = match reached.get(&next) {
+                None => next_i,
+                Some(known) if *known == next_i || known.is_none() => continue,
+                // Reached with different values of I, walk it again without assuming one
+                Some(_) => None,
+            };
+            reached.insert(next, state);
+            queue.push_back((next, state));
+        }
+    }
+    reached
+}
+
+/* The opcodes of the reachable instructions inside the ROM, in address order */
+fn reachable_opcodes(chip8: &ChipContext) -> Vec<(u16, u16)> {
+    let program_end = ChipContext::PROGRAM_START + chip8.program_size as u16;
+    reachable_code(chip8)
+        .into_keys()
+        .filter(|address| (ChipContext::PROGRAM_START..program_end).contains(address))
+        .map(|address| (address, chip8.opcode_at(address)))
+        .collect()
+}
+
+fn load(rom: &[u8]) -> Option<ChipContext> {
+    let mut chip8 = ChipContext::reset();
+    chip8.load_program_bytes(rom).ok()?;
+    Some(chip8)
+}
+
+/* Guesses the platform from the reachable instructions */
+pub fn detect_platform(rom: &[u8]) -> Platform {
+    let Some(chip8) = load(rom) else {
+        return Platform::XoChip;
+    };
+
+    let mut platform = Platform::Chip8;
+    for (_, opcode) in reachable_opcodes(&chip8) {
+        if is_xo_chip_opcode(opcode) {
+            return Platform::XoChip;
+        }
+        if is_super_chip_opcode(opcode) {
+            platform = Platform::SuperChip;
+        }
+    }
+    platform
+}
+
+/* Quirks picked for a ROM missing from the database, with the evidence */
+pub struct QuirkGuess {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub reasons: Vec<String>,
+}
+
+// How many instructions after Fx55/Fx65 are searched for a use of the moved I
+const LOAD_STORE_WINDOW: usize = 4;
+
+/* Starts from the profile of the platform the reachable instructions
+ * need. Plain CHIP-8 ROMs keep the emulator defaults unless the code only
+ * works with the COSMAC VIP behaviour: shifting Vy into a different Vx,
+ * or a second Fx55/Fx65 that continues where the previous one left I.
+ */
+pub fn guess_quirks(rom: &[u8]) -> QuirkGuess {
+    let Some(chip8) = load(rom) else {
+        return QuirkGuess {
+            platform: Platform::XoChip,
+            quirks: Quirks::XO_CHIP,
+            reasons: vec![format!("{} bytes only fit in XO-CHIP memory", rom.len())],
+        };
+    };
+    let opcodes = reachable_opcodes(&chip8);
+
+    if let Some((address, opcode)) = opcodes.iter().find(|(_, opcode)| is_xo_chip_opcode(*opcode)) {
+        return QuirkGuess {
+            platform: Platform::XoChip,
+            quirks: Quirks::XO_CHIP,
+            reasons: vec![format!("XO-CHIP opcode {:04X} at {:03X}", opcode, address)],
+        };
+    }
+    if let Some((address, opcode)) = opcodes.iter().find(|(_, opcode)| is_super_chip_opcode(*opcode)) {
+        return QuirkGuess {
+            platform: Platform::SuperChip,
+            quirks: Quirks::SUPER_CHIP,
+            reasons: vec![format!("SCHIP opcode {:04X} at {:03X}", opcode, address)],
+        };
+    }
+
+    let mut quirks = Quirks::DEFAULT;
+    let mut reasons = Vec::new();
+
+    let shift = opcodes.iter().find(|(_, opcode)| {
+        get_byte_0xF000(*opcode) == 0x8
+            && matches!(get_byte_0x000F(*opcode), 0x6 | 0xE)
+            && get_byte_0x0F00(*opcode) != get_byte_0x00F0(*opcode)
+    });
+    if let Some((address, opcode)) = shift {
+        quirks.shift = false;
+        reasons.push(format!("{:04X} at {:03X} shifts Vy into a different Vx", opcode, address));
+    }
+
+    let is_load_store = |opcode: u16| get_byte_0xF000(opcode) == 0xF && matches!(get_bytes_0x00FF(opcode), 0x55 | 0x65);
+    let continued = opcodes.iter().enumerate().filter(|(_, (_, opcode))| is_load_store(*opcode)).find_map(|(index, first)| {
+        let mut previous = first.0;
+        for following in opcodes[index + 1..].iter().take(LOAD_STORE_WINDOW) {
+            let (address, opcode) = *following;
+            // Only straight-line code continues with the I left behind
+            if address != previous + 2 {
+                return None;
+            }
+            if is_load_store(opcode) {
+                return Some((*first, *following));
+            }
+            // I is set again or the path leaves, whatever follows doesn't tell
+            let sets_i = get_byte_0xF000(opcode) == 0xA
+                || (get_byte_0xF000(opcode) == 0xF && matches!(get_bytes_0x00FF(opcode), 0x1E | 0x29));
+            let leaves = matches!(get_byte_0xF000(opcode), 0x1 | 0x2 | 0xB) || opcode == 0x00EE;
+            if sets_i || leaves {
+                return None;
+            }
+            previous = address;
+        }
+        None
+    });
+    if let Some(((first_address, first_opcode), (second_address, second_opcode))) = continued {
+        quirks.memory_leave_i_unchanged = false;
+        reasons.push(format!("{:04X} at {:03X} continues at the I left by {:04X} at {:03X}",
+            second_opcode, second_address, first_opcode, first_address));
+    }
+
+    if reasons.is_empty() {
+        reasons.push(String::from("no platform-specific or quirk-sensitive patterns in the reachable code"));
+    }
+    QuirkGuess {
+        platform: Platform::Chip8,
+        quirks,
+        reasons,
+    }
+}
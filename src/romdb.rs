@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::Quirks;
+use crate::palette::Palette;
+use crate::rom::Platform;
+
+/* Per-game settings found in the database for a ROM */
+pub struct RomSettings {
+    pub title: String,
+    pub platform_id: String,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub tick_rate: Option<u32>,
+    pub colors: Vec<String>,
+    pub key_hints: Vec<(String, u8)>,
+}
+
+/* Local copy of the community chip-8-database (programs.json,
+ * sha1-hashes.json and optionally platforms.json), ROMs are looked up by
+ * the SHA-1 of their bytes
+ */
+pub struct RomDatabase {
+    programs: Vec<Value>,
+    hashes: HashMap<String, usize>,
+    platforms: HashMap<String, Value>,
+}
+
+impl RomDatabase {
+    pub fn empty() -> RomDatabase {
+        RomDatabase {
+            programs: Vec::new(),
+            hashes: HashMap::new(),
+            platforms: HashMap::new(),
+        }
+    }
+
+    fn read_json(path: &Path) -> Result<Value, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        serde_json::from_str(&text).map_err(|error| format!("cannot parse {}: {}", path.display(), error))
+    }
+
+    pub fn load(directory: &Path) -> Result<RomDatabase, String> {
+        let mut database = RomDatabase::empty();
+
+        if let Value::Array(programs) = Self::read_json(&directory.join("programs.json"))? {
+            database.programs = programs;
+        }
+        if let Value::Object(hashes) = Self::read_json(&directory.join("sha1-hashes.json"))? {
+            for (hash, index) in hashes {
+                if let Some(index) = index.as_u64() {
+                    database.hashes.insert(hash.to_lowercase(), index as usize);
+                }
+            }
+        }
+
+        let platforms_path = directory.join("platforms.json");
+        if platforms_path.exists() {
+            if let Value::Array(platforms) = Self::read_json(&platforms_path)? {
+                for platform in platforms {
+                    if let Some(id) = platform["id"].as_str() {
+                        database.platforms.insert(String::from(id), platform.clone());
+                    }
+                }
+            }
+        }
+
+        Ok(database)
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn sha1(rom: &[u8]) -> String {
+        sha1_smol::Sha1::from(rom).digest().to_string()
+    }
+
+    fn platform_for_id(id: &str) -> Platform {
+        match id {
+            "superchip1" | "superchip" | "chip48" | "megachip8" => Platform::SuperChip,
+            "xochip" => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+
+    fn default_quirks_for_id(id: &str) -> Quirks {
+        match id {
+            "modernChip8" => Quirks {
+                vblank: false,
+                logic: false,
+                ..Quirks::COSMAC_VIP
+            },
+            "chip48" | "superchip1" => Quirks {
+                memory_increment_by_x: true,
+                memory_leave_i_unchanged: false,
+                ..Quirks::SUPER_CHIP
+            },
+            _ => Quirks::for_platform(Self::platform_for_id(id)),
+        }
+    }
+
+    fn apply_quirks(quirks: &mut Quirks, values: &Value) {
+        let fields: [(&str, &mut bool); 7] = [
+            ("shift", &mut quirks.shift),
+            ("memoryIncrementByX", &mut quirks.memory_increment_by_x),
+            ("memoryLeaveIUnchanged", &mut quirks.memory_leave_i_unchanged),
+            ("wrap", &mut quirks.wrap),
+            ("jump", &mut quirks.jump),
+            ("vblank", &mut quirks.vblank),
+            ("logic", &mut quirks.logic),
+        ];
+        for (name, field) in fields {
+            if let Some(value) = values[name].as_bool() {
+                *field = value;
+            }
+        }
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomSettings> {
+        let hash = Self::sha1(rom);
+        let program = self.programs.get(*self.hashes.get(&hash)?)?;
+        let entry = &program["roms"][&hash];
+
+        let platform_id = entry["platforms"][0].as_str().unwrap_or("originalChip8");
+        let mut quirks = Self::default_quirks_for_id(platform_id);
+        if let Some(platform) = self.platforms.get(platform_id) {
+            Self::apply_quirks(&mut quirks, &platform["quirks"]);
+        }
+        Self::apply_quirks(&mut quirks, &entry["quirkyPlatforms"][platform_id]);
+
+        let tick_rate = entry["tickrate"]
+            .as_u64()
+            .or_else(|| self.platforms.get(platform_id)?["defaultTickrate"].as_u64())
+            .map(|tick_rate| tick_rate as u32);
+
+        let colors = match entry["colors"]["pixels"].as_array() {
+            Some(pixels) => pixels.iter().filter_map(|color| color.as_str()).map(String::from).collect(),
+            None => Vec::new(),
+        };
+
+        let mut key_hints = Vec::new();
+        if let Some(keys) = entry["keys"].as_object() {
+            for (action, key) in keys {
+                if let Some(key) = key.as_u64() {
+                    key_hints.push((action.clone(), (key & 0xF) as u8));
+                }
+            }
+        }
+
+        Some(RomSettings {
+            title: program["title"].as_str().unwrap_or("unknown").to_string(),
+            platform_id: String::from(platform_id),
+            platform: Self::platform_for_id(platform_id),
+            quirks,
+            tick_rate,
+            colors,
+            key_hints,
+        })
+    }
+}
+
+impl RomSettings {
+    /* Palette from the database colors, None unless there are at least a
+     * background and a foreground color
+     */
+    pub fn palette(&self, base: &Palette) -> Option<Palette> {
+        // XO-CHIP entries can list up to 16 colors, only 4 are ever shown
+        let colors: Vec<&str> = self.colors.iter().take(4).map(|color| color.as_str()).collect();
+        let mut palette = Palette::parse_custom(&colors.join(","), base)?;
+        palette.name = self.title.clone();
+        Some(palette)
+    }
+}
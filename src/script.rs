@@ -0,0 +1,185 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Dynamic, Engine, FuncArgs, Map, Scope, AST};
+
+use crate::ChipContext;
+
+/* Hooks a script can define, with their number of parameters */
+const HOOKS: [(&str, usize); 5] = [
+    ("on_start", 0),
+    ("on_frame", 0),
+    ("on_instruction", 2),
+    ("on_memory_write", 2),
+    ("on_key", 2),
+];
+
+/* A Rhai script with hooks called by the emulator. The hooks see a copy of
+ * the machine through the registered functions, changes are copied back
+ * after the hook returns. `this` is a map kept between hook calls.
+ */
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    hooks: HashSet<&'static str>,
+    machine: Rc<RefCell<ChipContext>>,
+    // Set by the functions that change the machine
+    modified: Rc<Cell<bool>>,
+    frame: Rc<Cell<i64>>,
+}
+
+fn register_api(engine: &mut Engine, machine: &Rc<RefCell<ChipContext>>, modified: &Rc<Cell<bool>>, frame: &Rc<Cell<i64>>) {
+    let chip = machine.clone();
+    engine.register_fn("peek", move |address: i64| chip.borrow().memory[address as usize & 0xFFF] as i64);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("poke", move |address: i64, value: i64| {
+        chip.borrow_mut().memory[address as usize & 0xFFF] = value as u8;
+        changed.set(true);
+    });
+
+    let chip = machine.clone();
+    engine.register_fn("reg", move |register: i64| chip.borrow().registers[register as usize & 0xF] as i64);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("set_reg", move |register: i64, value: i64| {
+        chip.borrow_mut().registers[register as usize & 0xF] = value as u8;
+        changed.set(true);
+    });
+
+    let chip = machine.clone();
+    engine.register_fn("get_i", move || chip.borrow().I as i64);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("set_i", move |value: i64| {
+        chip.borrow_mut().I = value as u16 & 0xFFF;
+        changed.set(true);
+    });
+    let chip = machine.clone();
+    engine.register_fn("get_pc", move || chip.borrow().PC as i64);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("set_pc", move |value: i64| {
+        chip.borrow_mut().PC = (value as u16 & 0xFFF).min(ChipContext::MAX_PC);
+        changed.set(true);
+    });
+    let chip = machine.clone();
+    engine.register_fn("get_sp", move || chip.borrow().SP as i64);
+    let chip = machine.clone();
+    engine.register_fn("get_dt", move || chip.borrow().delay_reg as i64);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("set_dt", move |value: i64| {
+        chip.borrow_mut().delay_reg = value as u8;
+        changed.set(true);
+    });
+    let chip = machine.clone();
+    engine.register_fn("get_st", move || chip.borrow().sound_reg as i64);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("set_st", move |value: i64| {
+        chip.borrow_mut().sound_reg = value as u8;
+        changed.set(true);
+    });
+
+    let chip = machine.clone();
+    engine.register_fn("key_down", move |key: i64| chip.borrow().keyboard_keys[key as usize & 0xF]);
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("press", move |key: i64| {
+        chip.borrow_mut().keyboard_keys[key as usize & 0xF] = true;
+        changed.set(true);
+    });
+    let (chip, changed) = (machine.clone(), modified.clone());
+    engine.register_fn("release", move |key: i64| {
+        chip.borrow_mut().keyboard_keys[key as usize & 0xF] = false;
+        changed.set(true);
+    });
+
+    let frame = frame.clone();
+    engine.register_fn("frame", move || frame.get());
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script, String> {
+        let machine = Rc::new(RefCell::new(ChipContext::reset()));
+        let modified = Rc::new(Cell::new(false));
+        let frame = Rc::new(Cell::new(0));
+
+        let mut engine = Engine::new();
+        register_api(&mut engine, &machine, &modified, &frame);
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+        let hooks = HOOKS
+            .iter()
+            .filter(|(name, parameters)| ast.iter_functions().any(|function| function.name == *name && function.params.len() == *parameters))
+            .map(|(name, _)| *name)
+            .collect();
+
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+        Ok(Script {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from_map(Map::new()),
+            hooks,
+            machine,
+            modified,
+            frame,
+        })
+    }
+
+    pub fn has_hook(&self, hook: &str) -> bool {
+        self.hooks.contains(hook)
+    }
+
+    fn call(&mut self, chip8: &mut ChipContext, hook: &'static str, args: impl FuncArgs) {
+        if !self.hooks.contains(hook) {
+            return;
+        }
+        self.machine.borrow_mut().clone_from(chip8);
+        self.modified.set(false);
+
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .rewind_scope(false)
+            .bind_this_ptr(&mut self.state);
+        let result = self.engine.call_fn_with_options::<Dynamic>(options, &mut self.scope, &self.ast, hook, args);
+        if let Err(error) = result {
+            // A broken hook would print the same error every instruction
+            eprintln!("script error in {}, hook disabled: {}", hook, error);
+            self.hooks.remove(hook);
+        }
+
+        if self.modified.get() {
+            chip8.clone_from(&self.machine.borrow());
+        }
+    }
+
+    pub fn rom_started(&mut self, chip8: &mut ChipContext) {
+        self.frame.set(0);
+        self.call(chip8, "on_start", ());
+    }
+
+    pub fn frame_done(&mut self, chip8: &mut ChipContext) {
+        self.call(chip8, "on_frame", ());
+        self.frame.set(self.frame.get() + 1);
+    }
+
+    /* Call before the instruction at PC runs */
+    pub fn before_instruction(&mut self, chip8: &mut ChipContext) {
+        let (pc, opcode) = (chip8.PC as i64, chip8.fetch_opcode() as i64);
+        self.call(chip8, "on_instruction", (pc, opcode));
+    }
+
+    pub fn memory_written(&mut self, chip8: &mut ChipContext, address: u16, value: u8) {
+        self.call(chip8, "on_memory_write", (address as i64, value as i64));
+    }
+
+    pub fn key_changed(&mut self, chip8: &mut ChipContext, key: usize, pressed: bool) {
+        self.call(chip8, "on_key", (key as i64, pressed));
+    }
+}
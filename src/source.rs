@@ -0,0 +1,210 @@
+use std::io::{Cursor, Read};
+use std::path::{Component, Path};
+use std::sync::OnceLock;
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZIP_MAGIC: [u8; 4] = *b"PK\x03\x04";
+
+// Files in archives that are not ROMs
+const IGNORED_EXTENSIONS: [&str; 8] = ["txt", "md", "nfo", "json", "8o", "png", "gif", "jpg"];
+
+// Stdin can only be read once, resets and reloads get the same bytes again
+static STDIN: OnceLock<Vec<u8>> = OnceLock::new();
+
+fn is_stdin(path: &Path) -> bool {
+    path.components().eq([Component::Normal("-".as_ref())])
+}
+
+fn read_stdin() -> Result<Vec<u8>, String> {
+    if let Some(bytes) = STDIN.get() {
+        return Ok(bytes.clone());
+    }
+    let mut bytes = Vec::new();
+    std::io::stdin().read_to_end(&mut bytes).map_err(|error| format!("cannot read stdin: {}", error))?;
+    Ok(STDIN.get_or_init(|| bytes).clone())
+}
+
+/* The bytes of a ROM file, "-" for stdin. Gzip files are decompressed and
+ * zip archives opened. A ROM in an archive holding several is named as if
+ * the archive was a directory, games.zip/pong.ch8.
+ */
+pub fn read(path: &Path) -> Result<Vec<u8>, String> {
+    if is_stdin(path) {
+        return unpack(path, read_stdin()?, None);
+    }
+    let error = match std::fs::read(path) {
+        Ok(bytes) => return unpack(path, bytes, None),
+        Err(error) => error,
+    };
+
+    // games.zip/pong.ch8 doesn't exist as a file, look for the archive above it
+    for archive in path.ancestors().skip(1).filter(|archive| !archive.as_os_str().is_empty()) {
+        if is_stdin(archive) || archive.is_file() {
+            let entry = path.strip_prefix(archive)
+                .unwrap_or(path)
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let bytes = if is_stdin(archive) {
+                read_stdin()?
+            }
+            else {
+                std::fs::read(archive).map_err(|error| format!("cannot read {}: {}", archive.display(), error))?
+            };
+            return unpack(archive, bytes, Some(entry));
+        }
+    }
+    Err(format!("cannot read {}: {}", path.display(), error))
+}
+
+fn gunzip(path: &Path, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let mut unpacked = Vec::new();
+    GzDecoder::new(bytes)
+        .read_to_end(&mut unpacked)
+        .map_err(|error| format!("cannot decompress {}: {}", path.display(), error))?;
+    Ok(unpacked)
+}
+
+fn unpack(path: &Path, bytes: Vec<u8>, entry: Option<String>) -> Result<Vec<u8>, String> {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        // A gzipped zip archive is still an archive
+        return unpack(path, gunzip(path, &bytes)?, entry);
+    }
+    if bytes.starts_with(&ZIP_MAGIC) {
+        return read_zip(path, bytes, entry);
+    }
+    match entry {
+        Some(entry) => Err(format!("cannot read {}/{}: {} is not a zip archive", path.display(), entry, path.display())),
+        None => Ok(bytes),
+    }
+}
+
+fn read_zip(path: &Path, bytes: Vec<u8>, entry: Option<String>) -> Result<Vec<u8>, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|error| format!("{}: {}", path.display(), error))?;
+    let name = match entry {
+        Some(name) => name,
+        None => match rom_names(&archive).as_slice() {
+            [] => return Err(format!("{} holds no ROMs", path.display())),
+            [rom] => rom.clone(),
+            roms => return Err(format!("{} holds {} ROMs, pick one with {}/NAME: {}",
+                path.display(), roms.len(), path.display(), roms.join(", "))),
+        },
+    };
+
+    let mut file = archive.by_name(&name).map_err(|_| format!("{} has no {}", path.display(), name))?;
+    let mut rom = Vec::new();
+    file.read_to_end(&mut rom).map_err(|error| format!("cannot unpack {}/{}: {}", path.display(), name, error))?;
+    Ok(rom)
+}
+
+fn rom_names(archive: &ZipArchive<Cursor<Vec<u8>>>) -> Vec<String> {
+    let mut roms: Vec<String> = archive.file_names()
+        .filter(|name| !name.ends_with('/') && !name.starts_with("__MACOSX/"))
+        .filter(|name| {
+            let extension = Path::new(name).extension().unwrap_or_default().to_string_lossy().to_lowercase();
+            !IGNORED_EXTENSIONS.contains(&extension.as_str())
+        })
+        .map(String::from)
+        .collect();
+    roms.sort();
+    roms
+}
+
+/* The ROMs in a zip archive, gzipped or not, empty for any other file */
+pub fn archive_roms(path: &Path) -> Vec<String> {
+    let Ok(mut bytes) = std::fs::read(path) else {
+        return Vec::new();
+    };
+    if bytes.starts_with(&GZIP_MAGIC) {
+        match gunzip(path, &bytes) {
+            Ok(unpacked) => bytes = unpacked,
+            Err(_) => return Vec::new(),
+        }
+    }
+    if !bytes.starts_with(&ZIP_MAGIC) {
+        return Vec::new();
+    }
+    match ZipArchive::new(Cursor::new(bytes)) {
+        Ok(archive) => rom_names(&archive),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    const ROM_A: [u8; 4] = [0x00, 0xE0, 0x12, 0x00];
+    const ROM_B: [u8; 2] = [0x12, 0x00];
+
+    fn fixture(name: &str, bytes: &[u8]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("chip8-source-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, bytes) in files {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn plain_and_gzip_files() {
+        assert_eq!(read(&fixture("plain.ch8", &ROM_A)).unwrap(), ROM_A);
+        assert_eq!(read(&fixture("plain.ch8.gz", &gzip(&ROM_A))).unwrap(), ROM_A);
+        assert!(archive_roms(&fixture("rom.ch8", &ROM_A)).is_empty());
+    }
+
+    #[test]
+    fn zip_with_one_rom_skips_other_files() {
+        let path = fixture("one.zip", &zip(&[("readme.txt", b"hello"), ("pong.ch8", &ROM_A)]));
+        assert_eq!(read(&path).unwrap(), ROM_A);
+        assert_eq!(archive_roms(&path), ["pong.ch8"]);
+    }
+
+    #[test]
+    fn zip_with_several_roms_needs_an_entry() {
+        let path = fixture("two.zip", &zip(&[("b.ch8", &ROM_B), ("games/a.ch8", &ROM_A)]));
+        assert_eq!(archive_roms(&path), ["b.ch8", "games/a.ch8"]);
+        assert!(read(&path).unwrap_err().contains("holds 2 ROMs"));
+        assert_eq!(read(&path.join("b.ch8")).unwrap(), ROM_B);
+        assert_eq!(read(&path.join("games").join("a.ch8")).unwrap(), ROM_A);
+        assert!(read(&path.join("c.ch8")).unwrap_err().contains("has no c.ch8"));
+    }
+
+    #[test]
+    fn gzipped_zip() {
+        let path = fixture("two.zip.gz", &gzip(&zip(&[("a.ch8", &ROM_A), ("b.ch8", &ROM_B)])));
+        assert_eq!(archive_roms(&path), ["a.ch8", "b.ch8"]);
+        assert_eq!(read(&path.join("b.ch8")).unwrap(), ROM_B);
+    }
+
+    #[test]
+    fn entry_of_a_file_that_is_no_archive() {
+        let path = fixture("not.zip", &ROM_A);
+        assert!(read(&path.join("a.ch8")).unwrap_err().contains("is not a zip archive"));
+    }
+}
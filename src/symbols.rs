@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/* Labels for addresses, loaded from the symbol file of an assembler */
+#[derive(Clone, Default)]
+pub struct SymbolMap {
+    by_address: BTreeMap<u16, String>,
+    by_name: HashMap<String, u16>,
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(hex, 16).ok().filter(|address| *address < 0x1000)
+}
+
+fn looks_numeric(text: &str) -> bool {
+    text.starts_with("0x") || text.starts_with("0X") || text.starts_with('$') || text.chars().all(|character| character.is_ascii_digit())
+}
+
+impl SymbolMap {
+    pub fn empty() -> SymbolMap {
+        SymbolMap::default()
+    }
+
+    /* Reads a JSON object of label -> address, as saved by Octo, or text
+     * with one "label address", "address label" or "label = address" per
+     * line. Text addresses are hex with an optional 0x or $ prefix.
+     */
+    pub fn load(path: &Path) -> Result<SymbolMap, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| format!("cannot read {}: {}", path.display(), error))?;
+        let mut symbols = SymbolMap::empty();
+
+        if text.trim_start().starts_with('{') {
+            let json: serde_json::Value = serde_json::from_str(&text)
+                .map_err(|error| format!("{}: {}", path.display(), error))?;
+            for (name, address) in json.as_object().into_iter().flatten() {
+                if let Some(address) = address.as_u64().filter(|address| *address < 0x1000) {
+                    symbols.insert(name, address as u16);
+                }
+            }
+            return Ok(symbols);
+        }
+
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split(['#', ';']).next().unwrap_or("");
+            let fields: Vec<&str> = line.split(|character: char| character.is_whitespace() || character == '=' || character == ':')
+                .filter(|field| !field.is_empty())
+                .collect();
+            match fields.as_slice() {
+                [] => {}
+                [first, second] => {
+                    // Labels like "add" are valid hex too, then the field that looks more like a number wins
+                    let entry = match (parse_address(first), parse_address(second)) {
+                        (Some(address), Some(_)) if looks_numeric(first) && !looks_numeric(second) => Some((*second, address)),
+                        (_, Some(address)) => Some((*first, address)),
+                        (Some(address), None) => Some((*second, address)),
+                        (None, None) => None,
+                    };
+                    match entry {
+                        Some((name, address)) => symbols.insert(name, address),
+                        None => return Err(format!("{}:{}: no address in \"{}\"", path.display(), line_number + 1, line.trim())),
+                    }
+                }
+                _ => return Err(format!("{}:{}: expected a label and an address", path.display(), line_number + 1)),
+            }
+        }
+        Ok(symbols)
+    }
+
+    fn insert(&mut self, name: &str, address: u16) {
+        self.by_address.insert(address, String::from(name));
+        self.by_name.insert(String::from(name), address);
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.by_address.get(&address).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    /* A label name or a hex address */
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        self.address(text).or_else(|| parse_address(text))
+    }
+
+    /* "label", "label+4" for an address after the closest label, or the
+     * bare address without a label before it
+     */
+    pub fn describe(&self, address: u16) -> String {
+        match self.by_address.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) => format!("{}+{}", name, address - start),
+            None => format!("{:03X}", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(name: &str, text: &str) -> Result<SymbolMap, String> {
+        let path = std::env::temp_dir().join(format!("chip8-symbols-{}-{}", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        let symbols = SymbolMap::load(&path);
+        let _ = std::fs::remove_file(&path);
+        symbols
+    }
+
+    #[test]
+    fn load_octo_json() {
+        let symbols = load("octo.json", r#"{"main": 512, "draw": 548, "beyond": 4096, "name": "text"}"#).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.address("main"), Some(0x200));
+        assert_eq!(symbols.label(0x224), Some("draw"));
+        assert_eq!(symbols.address("beyond"), None);
+    }
+
+    #[test]
+    fn load_text_formats() {
+        let text = "# labels\nmain 0x200\n$224 draw\nloop = 22A ; comment\n\nadd 230\n";
+        let symbols = load("labels.sym", text).unwrap();
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols.address("main"), Some(0x200));
+        assert_eq!(symbols.address("draw"), Some(0x224));
+        assert_eq!(symbols.address("loop"), Some(0x22A));
+        assert_eq!(symbols.address("add"), Some(0x230));
+        assert_eq!(symbols.describe(0x22C), "loop+2");
+        assert_eq!(symbols.describe(0x100), "100");
+        assert_eq!(symbols.resolve("draw"), Some(0x224));
+        assert_eq!(symbols.resolve("2B0"), Some(0x2B0));
+    }
+
+    #[test]
+    fn load_text_errors() {
+        assert!(load("three.sym", "main 200 extra\n").err().unwrap().contains(":1:"));
+        assert!(load("none.sym", "main\nloop zzz\n").is_err());
+    }
+}
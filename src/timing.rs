@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use crate::{get_byte_0x000F, get_byte_0x00F0, get_byte_0x0F00, get_byte_0xF000, get_bytes_0x00FF};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TimingModel {
+    // Every instruction takes 1 / instructions per second
+    Fixed,
+    // Every instruction takes as long as in the COSMAC VIP interpreter
+    CosmacVip,
+}
+
+impl TimingModel {
+    pub fn from_name(name: &str) -> Option<TimingModel> {
+        match name {
+            "fixed" => Some(TimingModel::Fixed),
+            "vip" => Some(TimingModel::CosmacVip),
+            _ => None,
+        }
+    }
+}
+
+/* Approximate execution times in microseconds of the COSMAC VIP interpreter
+ * routines, one 1802 machine cycle is 8 clocks at 1.76 MHz (~4.54us).
+ * Routines that loop (sprites, BCD, register load/store) add a cost per iteration.
+ */
+struct VipTimes;
+impl VipTimes {
+    const CLS: u32 = 109;
+    const RET: u32 = 105;
+    const MACHINE_CODE: u32 = 105;
+    const JUMP: u32 = 105;
+    const CALL: u32 = 105;
+    const SKIP: u32 = 46;
+    const SKIP_TAKEN: u32 = 64;
+    const LOAD_IMMEDIATE: u32 = 27;
+    const ADD_IMMEDIATE: u32 = 45;
+    const ARITHMETIC: u32 = 200;
+    const LOAD_I: u32 = 55;
+    const JUMP_OFFSET: u32 = 105;
+    const RANDOM: u32 = 164;
+    const DRAW_SETUP: u32 = 170;
+    const DRAW_ROW: u32 = 90;
+    const DRAW_ROW_SHIFT: u32 = 15;
+    const KEY_SKIP: u32 = 73;
+    const TIMER: u32 = 45;
+    const WAIT_KEY: u32 = 45;
+    const ADD_I: u32 = 86;
+    const FONT: u32 = 91;
+    const BCD: u32 = 364;
+    const BCD_DIGIT_STEP: u32 = 47;
+    const LOAD_STORE: u32 = 64;
+    const LOAD_STORE_REGISTER: u32 = 34;
+}
+
+pub const FRAME_TIME: Duration = Duration::from_micros(1_000_000 / 60);
+
+/* Time the VIP needs for opcode, given the registers before it runs.
+ * Dxyn also waits for the next vertical blank, that part is left to the
+ * caller since it depends on where in the frame the draw happens.
+ */
+pub fn vip_instruction_time(opcode: u16, registers: &[u8; 16]) -> Duration {
+    let vx = registers[get_byte_0x0F00(opcode) as usize];
+    let vy = registers[get_byte_0x00F0(opcode) as usize];
+    let skip_time = |taken: bool| if taken { VipTimes::SKIP_TAKEN } else { VipTimes::SKIP };
+
+    let micros = match get_byte_0xF000(opcode) {
+        0x0 => match opcode {
+            0x00E0 => VipTimes::CLS,
+            0x00EE => VipTimes::RET,
+            _ => VipTimes::MACHINE_CODE,
+        },
+        0x1 => VipTimes::JUMP,
+        0x2 => VipTimes::CALL,
+        0x3 => skip_time(vx == get_bytes_0x00FF(opcode) as u8),
+        0x4 => skip_time(vx != get_bytes_0x00FF(opcode) as u8),
+        0x5 => skip_time(vx == vy),
+        0x6 => VipTimes::LOAD_IMMEDIATE,
+        0x7 => VipTimes::ADD_IMMEDIATE,
+        0x8 => VipTimes::ARITHMETIC,
+        0x9 => skip_time(vx != vy),
+        0xA => VipTimes::LOAD_I,
+        0xB => VipTimes::JUMP_OFFSET,
+        0xC => VipTimes::RANDOM,
+        0xD => {
+            // Rows not starting on a byte boundary are shifted bit by bit
+            let shift = (vx % 8) as u32;
+            VipTimes::DRAW_SETUP + get_byte_0x000F(opcode) as u32 * (VipTimes::DRAW_ROW + shift * VipTimes::DRAW_ROW_SHIFT)
+        }
+        0xE => VipTimes::KEY_SKIP,
+        _ => match get_bytes_0x00FF(opcode) {
+            0x07 | 0x15 | 0x18 => VipTimes::TIMER,
+            0x0A => VipTimes::WAIT_KEY,
+            0x1E => VipTimes::ADD_I,
+            0x29 => VipTimes::FONT,
+            // Digits are computed by repeated subtraction
+            0x33 => VipTimes::BCD + (vx / 100 + vx / 10 % 10 + vx % 10) as u32 * VipTimes::BCD_DIGIT_STEP,
+            0x55 | 0x65 => VipTimes::LOAD_STORE + (get_byte_0x0F00(opcode) as u32 + 1) * VipTimes::LOAD_STORE_REGISTER,
+            _ => VipTimes::MACHINE_CODE,
+        },
+    };
+    Duration::from_micros(micros as u64)
+}
+
+pub fn is_draw(opcode: u16) -> bool {
+    get_byte_0xF000(opcode) == 0xD
+}
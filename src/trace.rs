@@ -0,0 +1,259 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::disasm;
+use crate::symbols::SymbolMap;
+use crate::{get_byte_0xF000, ChipContext};
+
+/* Machine state before one instruction plus the memory it wrote, one line
+ * of a trace file:
+ *
+ *   cycle pc op v0 .. vf i sp dt st keys writes ; mnemonic
+ *
+ * All numbers but the cycle are hex, keys is a bit mask of the pressed keys
+ * (bit n = key n) and writes lists addr=value pairs or "-".
+ */
+#[derive(Clone, PartialEq, Debug)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay: u8,
+    pub sound: u8,
+    pub keys: u16,
+    pub writes: Vec<(u16, u8)>,
+}
+
+pub const HEADER: &str = "# cycle pc op v0 v1 v2 v3 v4 v5 v6 v7 v8 v9 va vb vc vd ve vf i sp dt st keys writes ; mnemonic";
+
+impl TraceRecord {
+    pub fn capture(chip8: &ChipContext, cycle: u64) -> TraceRecord {
+        let mut keys: u16 = 0;
+        for (key, pressed) in chip8.keyboard_keys.iter().enumerate() {
+            if *pressed {
+                keys |= 1 << key;
+            }
+        }
+
+        TraceRecord {
+            cycle,
+            pc: chip8.PC,
+            opcode: chip8.fetch_opcode(),
+            registers: chip8.registers,
+            i: chip8.I,
+            sp: chip8.SP,
+            delay: chip8.delay_reg,
+            sound: chip8.sound_reg,
+            keys,
+            writes: Vec::new(),
+        }
+    }
+
+    /* Fills in the bytes written by Fx33/Fx55, call after the instruction ran */
+    pub fn capture_writes(&mut self, chip8: &ChipContext) {
+        self.writes = chip8.memory_writes(self.opcode, self.i);
+    }
+
+    /* The mnemonic comment gets the label of PC and of jump targets */
+    pub fn format(&self, symbols: &SymbolMap) -> String {
+        let registers: Vec<String> = self.registers.iter().map(|register| format!("{:02X}", register)).collect();
+        let writes = if self.writes.is_empty() {
+            String::from("-")
+        }
+        else {
+            let writes: Vec<String> = self.writes.iter().map(|(address, value)| format!("{:03X}={:02X}", address, value)).collect();
+            writes.join(",")
+        };
+        let mut mnemonic = disasm::disassemble_labeled(self.opcode, symbols);
+        if let Some(label) = symbols.label(self.pc) {
+            mnemonic = format!("{}: {}", label, mnemonic);
+        }
+        format!("{} {:03X} {:04X} {} {:03X} {:X} {:02X} {:02X} {:04X} {} ; {}",
+            self.cycle, self.pc, self.opcode, registers.join(" "), self.i, self.sp,
+            self.delay, self.sound, self.keys, writes, mnemonic)
+    }
+
+    /* Parses a line written by format(), None for comments and blank lines.
+     * Columns after st are optional so traces from emulators without key or
+     * write columns can still be read.
+     */
+    pub fn parse(line: &str) -> Option<Result<TraceRecord, String>> {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        if columns.len() < 23 {
+            return Some(Err(format!("expected at least 23 columns, found {}", columns.len())));
+        }
+        let hex = |index: usize| u16::from_str_radix(columns[index], 16)
+            .map_err(|_| format!("invalid hex value \"{}\" in column {}", columns[index], index + 1));
+
+        let parsed = (|| {
+            let mut registers = [0u8; 16];
+            for (index, register) in registers.iter_mut().enumerate() {
+                *register = hex(3 + index)? as u8;
+            }
+
+            let mut writes = Vec::new();
+            if let Some(column) = columns.get(24).filter(|column| **column != "-") {
+                for write in column.split(',') {
+                    let (address, value) = write.split_once('=').ok_or(format!("invalid write \"{}\"", write))?;
+                    let address = u16::from_str_radix(address, 16).map_err(|_| format!("invalid write \"{}\"", write))?;
+                    let value = u8::from_str_radix(value, 16).map_err(|_| format!("invalid write \"{}\"", write))?;
+                    writes.push((address, value));
+                }
+            }
+
+            Ok(TraceRecord {
+                cycle: columns[0].parse().map_err(|_| format!("invalid cycle \"{}\"", columns[0]))?,
+                pc: hex(1)?,
+                opcode: hex(2)?,
+                registers,
+                i: hex(19)?,
+                sp: hex(20)? as u8,
+                delay: hex(21)? as u8,
+                sound: hex(22)? as u8,
+                keys: if columns.len() > 23 { hex(23)? } else { 0 },
+                writes,
+            })
+        })();
+        Some(parsed)
+    }
+}
+
+/* Writes a TraceRecord line for every executed instruction that passes the
+ * address range and opcode class filters
+ */
+pub struct Tracer {
+    writer: BufWriter<File>,
+    cycle: u64,
+    address_range: (u16, u16),
+    // Bit n set = trace opcodes whose first nibble is n
+    opcode_classes: u16,
+    pending: Option<TraceRecord>,
+    symbols: SymbolMap,
+}
+
+impl Tracer {
+    pub fn create(path: &Path, address_range: (u16, u16), opcode_classes: u16, symbols: SymbolMap) -> Result<Tracer, String> {
+        let file = File::create(path).map_err(|error| format!("cannot create {}: {}", path.display(), error))?;
+        let mut writer = BufWriter::new(file);
+        let _ = writeln!(writer, "{}", HEADER);
+        Ok(Tracer {
+            writer,
+            cycle: 0,
+            address_range,
+            opcode_classes,
+            pending: None,
+            symbols,
+        })
+    }
+
+    /* "200-3FF" */
+    pub fn parse_address_range(text: &str) -> Option<(u16, u16)> {
+        let (start, end) = text.split_once('-')?;
+        let start = u16::from_str_radix(start.trim_start_matches("0x"), 16).ok()?;
+        let end = u16::from_str_radix(end.trim_start_matches("0x"), 16).ok()?;
+        Some((start, end))
+    }
+
+    /* "8,D,F" */
+    pub fn parse_opcode_classes(text: &str) -> Option<u16> {
+        let mut classes = 0;
+        for class in text.split(',') {
+            classes |= 1 << u16::from_str_radix(class.trim(), 16).ok().filter(|class| *class < 16)?;
+        }
+        Some(classes)
+    }
+
+    pub fn rom_started(&mut self, name: &str) {
+        self.cycle = 0;
+        let _ = writeln!(self.writer, "# rom {}", name);
+    }
+
+    pub fn before_instruction(&mut self, chip8: &ChipContext) {
+        let record = TraceRecord::capture(chip8, self.cycle);
+        self.cycle += 1;
+        let class = get_byte_0xF000(record.opcode);
+        if record.pc >= self.address_range.0 && record.pc <= self.address_range.1 && self.opcode_classes & (1 << class) != 0 {
+            self.pending = Some(record);
+        }
+    }
+
+    pub fn after_instruction(&mut self, chip8: &ChipContext) {
+        if let Some(mut record) = self.pending.take() {
+            record.capture_writes(chip8);
+            let _ = writeln!(self.writer, "{}", record.format(&self.symbols));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> TraceRecord {
+        let mut registers = [0u8; 16];
+        for (index, register) in registers.iter_mut().enumerate() {
+            *register = index as u8 * 0x11;
+        }
+        TraceRecord {
+            cycle: 1234,
+            pc: 0x2A4,
+            opcode: 0xF355,
+            registers,
+            i: 0x3F0,
+            sp: 2,
+            delay: 0x3C,
+            sound: 0x01,
+            keys: 0x8001,
+            writes: vec![(0x3F0, 0x00), (0x3F1, 0x11)],
+        }
+    }
+
+    #[test]
+    fn format_parse_round_trip() {
+        let record = record();
+        let line = record.format(&SymbolMap::empty());
+        assert_eq!(TraceRecord::parse(&line), Some(Ok(record)));
+    }
+
+    #[test]
+    fn round_trip_without_writes() {
+        let mut record = record();
+        record.writes.clear();
+        let line = record.format(&SymbolMap::empty());
+        assert!(line.contains(" - ;"));
+        assert_eq!(TraceRecord::parse(&line), Some(Ok(record)));
+    }
+
+    #[test]
+    fn parse_without_key_and_write_columns() {
+        let line = "7 200 6A02 00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F 000 0 00 00";
+        let parsed = TraceRecord::parse(line).unwrap().unwrap();
+        assert_eq!(parsed.cycle, 7);
+        assert_eq!(parsed.pc, 0x200);
+        assert_eq!(parsed.opcode, 0x6A02);
+        assert_eq!(parsed.registers[0xF], 0x0F);
+        assert_eq!(parsed.keys, 0);
+        assert!(parsed.writes.is_empty());
+
+        let with_keys = format!("{} 0010", line);
+        assert_eq!(TraceRecord::parse(&with_keys).unwrap().unwrap().keys, 0x10);
+    }
+
+    #[test]
+    fn parse_skips_comments_and_rejects_short_lines() {
+        assert_eq!(TraceRecord::parse("# header"), None);
+        assert_eq!(TraceRecord::parse("   ; only a mnemonic"), None);
+        assert!(matches!(TraceRecord::parse("1 200 00E0"), Some(Err(_))));
+        let bad_write = "1 200 00E0 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 000 0 00 00 0000 3F0";
+        assert!(matches!(TraceRecord::parse(bad_write), Some(Err(_))));
+    }
+}
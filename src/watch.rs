@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/* Polls the modification time of a file, cheap enough to call every loop
+ * iteration since the file system is only asked once per interval
+ */
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    interval: Duration,
+    last_check: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path, interval: Duration) -> FileWatcher {
+        FileWatcher {
+            path: path.to_path_buf(),
+            modified: Self::modified_time(path),
+            interval,
+            last_check: Instant::now(),
+        }
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < self.interval {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = Self::modified_time(&self.path);
+        // A missing file is usually an editor replacing it, wait until it's back
+        if modified.is_none() || modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}